@@ -0,0 +1,527 @@
+use crate::{
+    not_found_as, temp_file_name, write_atomically_using, FskvError, MutationKind, Store,
+    CREATED_SIDECAR, VERSION_SIDECAR,
+};
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+
+/// Which end of a value `Store::append_bounded` keeps when it's too long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepEnd {
+    /// Keep the oldest data, dropping what doesn't fit at the end
+    Head,
+    /// Keep the most recent data, dropping the oldest
+    Tail,
+}
+
+impl Store {
+    /// Compute `dst` from the value of `src`: read `src`, apply `f` and
+    /// atomically write the result to `dst`, with `src` locked for reading
+    /// and `dst` for writing throughout.
+    pub fn derive(
+        &self,
+        dst: &str,
+        src: &str,
+        f: impl FnOnce(&str) -> String,
+    ) -> Result<(), FskvError> {
+        self.check_key(src)?;
+        let _writing = self.check_key_for_write(dst)?;
+        let _locks = self.lock_keys(&[(src, false), (dst, true)])?;
+        let value = f(&self.get(src)?);
+        self.write_locked(dst, &value)
+    }
+
+    /// Copy every key starting with `from_prefix` to the same key starting
+    /// with `to_prefix` instead, e.g. `a:1` to `b:1`, and return how many
+    /// were copied. Values are copied byte for byte, each with `put`, so
+    /// keys that already exist under `to_prefix` are left alone and not
+    /// counted; the copy as a whole isn't atomic, but it can be run again
+    /// to finish one that was cut short.
+    pub fn copy_prefix(&self, from_prefix: &str, to_prefix: &str) -> Result<usize, FskvError> {
+        // list them first, as the copies may well match too
+        let mut sources = Vec::new();
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            if let Some(rest) = key.strip_prefix(from_prefix) {
+                sources.push((format!("{}{}", to_prefix, rest), path));
+            }
+        }
+
+        let mut copied = 0;
+        for (to, path) in sources {
+            let value = match fs::read(path) {
+                Ok(value) => value,
+                // deleted since it was listed
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(FskvError::from(e)),
+            };
+            match self.put_encoded(&to, &value) {
+                Ok(()) => copied += 1,
+                Err(FskvError::KeyExists(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Write `value` to `key` only if `pred` holds for its current value
+    /// (`None` if there's no such key), returning whether it did; e.g. to
+    /// only move a state machine forward. The key is locked from the check
+    /// to the write, so nothing can change it in between.
+    pub fn put_if(
+        &self,
+        key: &str,
+        value: &str,
+        pred: impl Fn(Option<&str>) -> bool,
+    ) -> Result<bool, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let current = match self.get(key) {
+            Ok(current) => Some(current),
+            Err(e) if e.is_not_found() => None,
+            Err(e) => return Err(e),
+        };
+
+        if !pred(current.as_deref()) {
+            return Ok(false);
+        }
+        self.write_locked(key, value).map(|_| true)
+    }
+
+    /// Like `put`, but when the store is full (see `StoreBuilder::max_keys`)
+    /// delete the key `evict` picks to make room, as many times as needed;
+    /// e.g. to keep the store as a cache with an eviction policy of the
+    /// caller's. Fails with `FskvError::Full` once `evict` returns `None`.
+    pub fn put_with_eviction(
+        &self,
+        key: &str,
+        value: &str,
+        mut evict: impl FnMut() -> Option<String>,
+    ) -> Result<(), FskvError> {
+        loop {
+            match self.put(key, value) {
+                Err(FskvError::Full) => {
+                    let victim = evict().ok_or(FskvError::Full)?;
+                    match self.delete(&victim) {
+                        // someone else made room meanwhile
+                        Err(e) if e.is_not_found() => (),
+                        r => r?,
+                    }
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// The value of `key`, creating it with `value` if there's no such key,
+    /// along with whether this call created it; e.g. for initialization
+    /// that must happen once, by whoever gets there first. Callers racing
+    /// to create the same key all get the value of the one that did.
+    pub fn get_or_create(&self, key: &str, value: &str) -> Result<(String, bool), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        match self.get(key) {
+            Ok(current) => return Ok((current, false)),
+            Err(e) if e.is_not_found() => (),
+            Err(e) => return Err(e),
+        }
+
+        match self.put_locked(key, value.as_bytes()) {
+            Ok(()) => Ok((value.to_string(), true)),
+            // a plain `put`, which doesn't take the lock, got there first
+            Err(FskvError::KeyExists(_)) => self.get(key).map(|current| (current, false)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Change `key` to whatever `f` makes of its current value (`None` if
+    /// there's no such key): write what it returns, or delete the key if
+    /// it's `None`. The key is locked throughout; returns the old and the
+    /// new values.
+    pub fn modify(
+        &self,
+        key: &str,
+        f: impl FnOnce(Option<String>) -> Option<String>,
+    ) -> Result<(Option<String>, Option<String>), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let old = match self.get(key) {
+            Ok(old) => Some(old),
+            Err(e) if e.is_not_found() => None,
+            Err(e) => return Err(e),
+        };
+
+        let new = f(old.clone());
+        match (&old, &new) {
+            (_, Some(new)) => self.write_locked(key, new)?,
+            (Some(_), None) => self.delete_locked(key)?,
+            (None, None) => (),
+        }
+        Ok((old, new))
+    }
+
+    /// Delete the keys in `delete` and put the entries in `put` as a single
+    /// step, e.g. to move a state machine from one state key to another.
+    /// All the keys involved are locked throughout, and if any step fails
+    /// (a key to delete is missing, one to put already exists, ...) the
+    /// ones already done are undone, on a best-effort basis.
+    pub fn transition(&self, delete: &[&str], put: &[(&str, &str)]) -> Result<(), FskvError> {
+        let _writing = self.lock_store(false)?;
+        let mut keys = Vec::with_capacity(delete.len() + put.len());
+        for key in delete.iter().chain(put.iter().map(|(key, _)| key)) {
+            self.check_key_for_write(key)?;
+            keys.push((*key, true));
+        }
+        let _locks = self.lock_keys(&keys)?;
+
+        // what's needed to bring back the deleted keys
+        let mut deleted = Vec::new();
+        let mut created = Vec::new();
+
+        let result = (|| {
+            for key in delete {
+                let value = self.get_bytes(key)?;
+                let version = if self.versioned {
+                    self.read_version(key)?
+                } else {
+                    0
+                };
+                self.delete_locked(key)?;
+                deleted.push((*key, value, version));
+            }
+            for (key, value) in put {
+                self.put_locked(key, value.as_bytes())?;
+                created.push(*key);
+            }
+            Ok(())
+        })();
+
+        if result.is_err() {
+            // there's not much we can do if any of this fails too
+            for key in created {
+                self.delete_locked(key).unwrap_or(());
+            }
+            for (key, value, version) in deleted {
+                if self.versioned {
+                    self.write_version(key, version).unwrap_or(());
+                }
+                let options = self.value_file_options();
+                let restored = self.encode_value(&value, None).and_then(|stored| {
+                    write_atomically_using(
+                        self.backend.as_ref(),
+                        &options,
+                        &self.key_file(key),
+                        &stored,
+                    )
+                    .map_err(FskvError::from)
+                });
+                if restored.is_ok() {
+                    self.reserve_key().unwrap_or(());
+                    self.notify(key, MutationKind::Put);
+                }
+            }
+        }
+        result
+    }
+
+    /// Move the value of `from` to the new key `to`, failing if `to` already
+    /// exists, as it does when `from` and `to` are the same. `to` appears
+    /// with the whole value at once, and only then is `from` removed.
+    pub fn rename_key(&self, from: &str, to: &str) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(from)?;
+        self.check_key_for_write(to)?;
+        let _locks = self.lock_keys(&[(from, true), (to, true)])?;
+
+        if from == to {
+            // `to` is there already, if `from` is
+            fs::metadata(self.key_file(from)).map_err(|e| not_found_as(e, from))?;
+            return Err(FskvError::KeyExists(to.to_string()));
+        }
+
+        // linking never replaces an existing file
+        if let Err(e) = fs::hard_link(self.key_file(from), self.key_file(to)) {
+            return Err(match e.kind() {
+                ErrorKind::AlreadyExists => FskvError::KeyExists(to.to_string()),
+                _ => not_found_as(e, from),
+            });
+        }
+        // as with `put`, only once it's clear `to` is new
+        if let Err(e) = self.reserve_key() {
+            fs::remove_file(self.key_file(to)).unwrap_or(());
+            return Err(e);
+        }
+        if self.versioned {
+            self.write_version(to, 1)?;
+        }
+        self.move_created(from, to)?;
+        self.notify(to, MutationKind::Put);
+        self.delete_locked(from)
+    }
+
+    /// Like `rename_key`, but replacing the value of `to` if it exists.
+    /// Renaming a key to itself leaves it as it is.
+    pub fn rename_key_force(&self, from: &str, to: &str) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(from)?;
+        self.check_key_for_write(to)?;
+        let _locks = self.lock_keys(&[(from, true), (to, true)])?;
+        let from_path = self.key_file(from);
+        fs::metadata(&from_path).map_err(|e| not_found_as(e, from))?;
+        if from == to {
+            return Ok(());
+        }
+
+        let to_existed = self.key_file(to).exists();
+        // the value moves over the old one in one go, as with `update`
+        self.bump_version(to)?;
+        fs::rename(&from_path, self.key_file(to))?;
+        // the number of keys stays the same, or goes down by one
+        if to_existed {
+            self.release_key()?;
+        }
+        if self.versioned {
+            self.remove_sidecar(from, VERSION_SIDECAR)?;
+        }
+        self.move_created(from, to)?;
+        self.remove_variants(from)?;
+        self.remove_checksums(from)?;
+
+        self.notify(to, MutationKind::Update);
+        self.notify(from, MutationKind::Delete);
+        Ok(())
+    }
+
+    /// Like `rename_key`, but `to` gets the value of `from` as transformed
+    /// by `f`, e.g. to rename keys while moving their values to a new
+    /// format. Both keys are locked throughout.
+    pub fn rename_map(
+        &self,
+        from: &str,
+        to: &str,
+        f: impl FnOnce(String) -> String,
+    ) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(from)?;
+        self.check_key_for_write(to)?;
+        let _locks = self.lock_keys(&[(from, true), (to, true)])?;
+
+        if from == to {
+            // `to` is there already, if `from` is
+            fs::metadata(self.key_file(from)).map_err(|e| not_found_as(e, from))?;
+            return Err(FskvError::KeyExists(to.to_string()));
+        }
+
+        let value = f(self.get(from)?);
+        self.put_locked(to, value.as_bytes())?;
+        self.move_created(from, to)?;
+        self.delete_locked(from)
+    }
+
+    /// Remove `key` and return its value, or `None` if it isn't there (any
+    /// more); of several callers taking the same key at once, even from
+    /// different processes, exactly one gets it. E.g. for work queues where
+    /// each item should be processed once.
+    pub fn take_once(&self, key: &str) -> Result<Option<String>, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        // claim the file by moving it out of the way, which only one of
+        // the callers can do
+        let key_path = self.get_key_path(key);
+        let claimed = key_path.join(temp_file_name());
+        match fs::rename(self.key_file(key), &claimed) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FskvError::from(e)),
+        }
+
+        let value = fs::File::open(&claimed)
+            .map_err(FskvError::from)
+            .and_then(|file| {
+                let mut value = String::new();
+                self.value_reader(key, file)?.read_to_string(&mut value)?;
+                Ok(value)
+            });
+        fs::remove_file(&claimed)?;
+        self.release_key()?;
+        if self.versioned {
+            self.remove_sidecar(key, VERSION_SIDECAR)?;
+        }
+        self.remove_sidecar(key, CREATED_SIDECAR)?;
+        self.remove_variants(key)?;
+        self.remove_checksums(key)?;
+        self.notify(key, MutationKind::Delete);
+        Ok(Some(value?))
+    }
+
+    /// Apply `delta`, a binary diff made with `bsdiff::diff` from the current
+    /// value of `key` to its new one, and write the result as a whole; e.g.
+    /// to sync changes to large values without sending all of them. The
+    /// key is locked throughout, and the result has to be UTF-8, like any
+    /// other value.
+    pub fn patch(&self, key: &str, delta: &[u8]) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let current = self.get_bytes(key)?;
+
+        let mut patched = Vec::new();
+        bsdiff::patch(&current, &mut &delta[..], &mut patched).map_err(|e| {
+            FskvError::InvalidValue(format!("delta doesn't apply to {:?}: {}", key, e))
+        })?;
+        let patched = String::from_utf8(patched).map_err(|_| {
+            FskvError::InvalidValue(format!("patched value of {:?} isn't UTF-8", key))
+        })?;
+        self.write_locked(key, &patched)
+    }
+
+    /// Add `data` to the end of the value of `key`, creating it if needed.
+    /// Appends are serialized on the key's lock, but unlike `update` a
+    /// concurrent `get` may see one half-way through.
+    pub fn append(&self, key: &str, data: &str) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        self.append_locked(key, data)
+    }
+
+    /// Like `append`, but once adding `data` would take the value past
+    /// `max_bytes` the current value is rotated out first: it becomes
+    /// `key.1`, a previous `key.1` becomes `key.2` and so on, and `data`
+    /// starts a fresh value. Only the numbered keys' contents are rotated,
+    /// removing old ones is up to the caller.
+    pub fn append_rotating(&self, key: &str, data: &str, max_bytes: u64) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let size = match fs::metadata(self.key_file(key)) {
+            Ok(metadata) => metadata.len(),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(FskvError::from(e)),
+        };
+
+        if size > 0 && size + data.len() as u64 > max_bytes {
+            self.rotate(key)?;
+        }
+        self.append_locked(key, data)
+    }
+
+    /// Like `append`, but then cutting the value down to at most `max_bytes`
+    /// by dropping bytes from the other end than the one to `keep`, e.g. to
+    /// keep a rolling window of a log. It's never cut in the middle of a
+    /// character, so it may end up a few bytes shorter. Unlike plain
+    /// appends, those that cut the value replace it as a whole.
+    pub fn append_bounded(
+        &self,
+        key: &str,
+        data: &str,
+        max_bytes: u64,
+        keep: KeepEnd,
+    ) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let mut value = match self.get_bytes(key) {
+            Ok(value) => String::from_utf8(value)
+                .map_err(|_| FskvError::InvalidValue(format!("value of {:?} isn't UTF-8", key)))?,
+            Err(e) if e.is_not_found() => String::new(),
+            Err(e) => return Err(e),
+        };
+        let max = max_bytes.min(usize::MAX as u64) as usize;
+        if value.len() + data.len() <= max {
+            return self.append_locked(key, data);
+        }
+
+        value.push_str(data);
+        let value = match keep {
+            KeepEnd::Head => {
+                let mut end = max;
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                &value[..end]
+            }
+            KeepEnd::Tail => {
+                let mut start = value.len() - max;
+                while !value.is_char_boundary(start) {
+                    start += 1;
+                }
+                &value[start..]
+            }
+        };
+        self.write_locked(key, value)
+    }
+
+    /// Return the value of `key` and empty it (the key stays), or `None` if
+    /// there's no such key; e.g. to ship a buffer that's appended to. This
+    /// takes the same lock as appends, so each of them lands either in the
+    /// value returned or in the one left behind.
+    pub fn snapshot_and_clear(&self, key: &str) -> Result<Option<String>, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let value = match self.get(key) {
+            Ok(value) => value,
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        self.write_locked(key, "")?;
+        Ok(Some(value))
+    }
+
+    // shift key.N to key.N+1, ..., key to key.1, leaving no `key`
+    fn rotate(&self, key: &str) -> Result<(), FskvError> {
+        let rotated = |n: usize| format!("{}.{}", key, n);
+        let mut last = 0;
+        while fs::metadata(self.key_file(&rotated(last + 1))).is_ok() {
+            last += 1;
+        }
+
+        // oldest first, so nothing gets overwritten
+        for n in (0..=last).rev() {
+            let from = if n == 0 { key.to_string() } else { rotated(n) };
+            let to = rotated(n + 1);
+            let to_path = self.get_key_path(&to);
+            self.create_bucket(&to_path)?;
+            self.bump_version(&to)?;
+            self.in_bucket(&to_path, || {
+                fs::rename(self.key_file(&from), self.key_file(&to))
+            })?;
+            self.move_created(&from, &to)?;
+            self.remove_variants(&from)?;
+            self.remove_checksums(&from)?;
+            self.notify(&to, MutationKind::Update);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn append_locked(&self, key: &str, data: &str) -> Result<(), FskvError> {
+        let key_path = self.get_key_path(key);
+        self.create_bucket(&key_path)?;
+        let key_file = self.key_file(key);
+        let new_key = (self.max_keys.is_some() || self.creation_times) && !key_file.exists();
+        if new_key {
+            self.reserve_key()?;
+        }
+        let data = match self.encode_appended(key, data.as_bytes()) {
+            Ok(data) => data,
+            Err(e) => {
+                if new_key {
+                    self.release_key()?;
+                }
+                return Err(e);
+            }
+        };
+        self.bump_version(key)?;
+        // with the configured mode, should it create the file
+        let mut options = self.value_file_options();
+        options.create_new(false).create(true).append(true);
+        let appended = self
+            .in_bucket(&key_path, || options.open(&key_file))
+            .and_then(|mut f| f.write_all(&data));
+        if let Err(e) = appended {
+            if new_key {
+                self.release_key()?;
+            }
+            return Err(FskvError::from(e));
+        }
+        if new_key && self.creation_times {
+            self.write_created(key)?;
+        }
+        self.notify(key, MutationKind::Update);
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::io::Result;
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The file system operations behind `get`, `put`, `update` and `delete`,
+/// for `StoreBuilder::backend`; e.g. to fail some of them on purpose in
+/// tests. Every method defaults to doing just that on the local file
+/// system, so implementations only override the ones they care about.
+///
+/// Everything else (listing keys, locks, versions and other bookkeeping)
+/// always uses the local file system directly.
+pub trait Backend: Send + Sync {
+    /// Create `path` and any of its parents missing, with permissions
+    /// `mode` (on Unix) if given.
+    fn create_dir_all(&self, path: &Path, mode: Option<u32>) -> Result<()> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            builder.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        builder.create(path)
+    }
+
+    fn open(&self, path: &Path, options: &fs::OpenOptions) -> Result<fs::File> {
+        options.open(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<fs::Metadata> {
+        fs::metadata(path)
+    }
+}
+
+/// The local file system, which is what stores use unless given another
+/// `Backend`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsBackend;
+
+impl Backend for FsBackend {}
+
+// so a backend can be shared with the store, e.g. to change how it
+// behaves while the store is in use
+impl<B: Backend + ?Sized> Backend for Arc<B> {
+    fn create_dir_all(&self, path: &Path, mode: Option<u32>) -> Result<()> {
+        (**self).create_dir_all(path, mode)
+    }
+
+    fn open(&self, path: &Path, options: &fs::OpenOptions) -> Result<fs::File> {
+        (**self).open(path, options)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        (**self).rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        (**self).remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<fs::Metadata> {
+        (**self).metadata(path)
+    }
+}
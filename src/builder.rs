@@ -0,0 +1,649 @@
+use crate::coalesce::Coalescer;
+use crate::files::FileLimit;
+use crate::latency::Latencies;
+use crate::marker::{Marker, VALUE_CODECS_FORMAT_VERSION};
+use crate::ring::{Ring, MAX_RING_BUCKETS};
+use crate::validate::validate;
+use crate::{Backend, ChecksumAlgorithm, FsBackend, FskvError, KeyPartition, KeyValidator, Store};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Configure and open a `Store`, for when `Store::new` isn't enough.
+pub struct StoreBuilder {
+    root_directory: PathBuf,
+    create: bool,
+    key_validator: Option<KeyValidator>,
+    auto_decompress: bool,
+    trim_trailing_newlines: bool,
+    temp_dir: Option<PathBuf>,
+    key_partition: Option<KeyPartition>,
+    versioned: bool,
+    snapshots: bool,
+    validate_on_open: bool,
+    max_keys: Option<usize>,
+    value_extension: Option<String>,
+    strict_not_found: bool,
+    empty_is_absent: bool,
+    creation_times: bool,
+    key_salt: Option<String>,
+    ring_buckets: Option<u32>,
+    value_codecs: bool,
+    coalesce_window: Option<Duration>,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    record_latencies: bool,
+    max_open_files: Option<usize>,
+    verify_renames: bool,
+    checksum_retries: usize,
+    update_retries: usize,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    record_delimiter: Option<String>,
+    backend: Option<Arc<dyn Backend>>,
+}
+
+impl fmt::Debug for StoreBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StoreBuilder")
+            .field("root_directory", &self.root_directory)
+            .field("create", &self.create)
+            .field("key_validator", &self.key_validator.is_some())
+            .field("auto_decompress", &self.auto_decompress)
+            .field("trim_trailing_newlines", &self.trim_trailing_newlines)
+            .field("temp_dir", &self.temp_dir)
+            .field("key_partition", &self.key_partition.is_some())
+            .field("versioned", &self.versioned)
+            .field("snapshots", &self.snapshots)
+            .field("validate_on_open", &self.validate_on_open)
+            .field("max_keys", &self.max_keys)
+            .field("value_extension", &self.value_extension)
+            .field("strict_not_found", &self.strict_not_found)
+            .field("empty_is_absent", &self.empty_is_absent)
+            .field("creation_times", &self.creation_times)
+            .field("key_salt", &self.key_salt.is_some())
+            .field("ring_buckets", &self.ring_buckets)
+            .field("value_codecs", &self.value_codecs)
+            .field("coalesce_window", &self.coalesce_window)
+            .field("file_mode", &self.file_mode)
+            .field("dir_mode", &self.dir_mode)
+            .field("record_latencies", &self.record_latencies)
+            .field("max_open_files", &self.max_open_files)
+            .field("verify_renames", &self.verify_renames)
+            .field("checksum_retries", &self.checksum_retries)
+            .field("update_retries", &self.update_retries)
+            .field("checksum_algorithm", &self.checksum_algorithm)
+            .field("record_delimiter", &self.record_delimiter)
+            .field("backend", &self.backend.is_some())
+            .finish()
+    }
+}
+
+impl StoreBuilder {
+    pub fn new(root_dir: impl AsRef<Path>) -> StoreBuilder {
+        StoreBuilder {
+            root_directory: root_dir.as_ref().to_path_buf(),
+            create: false,
+            key_validator: None,
+            auto_decompress: false,
+            trim_trailing_newlines: false,
+            temp_dir: None,
+            key_partition: None,
+            versioned: false,
+            snapshots: false,
+            validate_on_open: false,
+            max_keys: None,
+            value_extension: None,
+            strict_not_found: false,
+            empty_is_absent: false,
+            creation_times: false,
+            key_salt: None,
+            ring_buckets: None,
+            value_codecs: false,
+            coalesce_window: None,
+            file_mode: None,
+            dir_mode: None,
+            record_latencies: false,
+            max_open_files: None,
+            verify_renames: false,
+            checksum_retries: 0,
+            update_retries: 0,
+            checksum_algorithm: None,
+            record_delimiter: None,
+            backend: None,
+        }
+    }
+
+    /// Create the root directory if it doesn't exist yet.
+    pub fn create(mut self, create: bool) -> StoreBuilder {
+        self.create = create;
+        self
+    }
+
+    /// Run `validator` on every key before it's written, in addition to the
+    /// built-in checks; an `Err` rejects the write with
+    /// `FskvError::InvalidKey` carrying the message.
+    pub fn key_validator(
+        mut self,
+        validator: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> StoreBuilder {
+        self.key_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Have `get` transparently decompress values that start with the gzip
+    /// magic bytes, e.g. while migrating legacy data that was compressed
+    /// externally; everything else is returned as is.
+    pub fn auto_decompress(mut self, auto_decompress: bool) -> StoreBuilder {
+        self.auto_decompress = auto_decompress;
+        self
+    }
+
+    /// Have `get` strip trailing newlines from values, so they read the same
+    /// however they were written. `get_bytes` still returns them as stored.
+    pub fn trim_trailing_newlines(mut self, trim: bool) -> StoreBuilder {
+        self.trim_trailing_newlines = trim;
+        self
+    }
+
+    /// Write the temporary file of each `update` in `temp_dir` rather than
+    /// next to the key, e.g. on a faster disk. If it's on the same file
+    /// system the file is still just renamed into place. Otherwise a rename
+    /// isn't possible, so it's first copied next to the key and renamed from
+    /// there: readers still never see a partial value, but every update
+    /// writes it twice, and a crash may leave files behind in `temp_dir`.
+    pub fn temp_dir(mut self, temp_dir: impl AsRef<Path>) -> StoreBuilder {
+        self.temp_dir = Some(temp_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Split each key into a shard part, which names the top-level
+    /// directory as is, and a part that's hashed for the levels below it.
+    /// This keeps related keys (e.g. all of a tenant's in `tenant:user:item`)
+    /// together. Keys whose shard part is empty or wouldn't be a valid key
+    /// are hashed whole.
+    ///
+    /// The store records that it was created with a partition function and
+    /// has to be opened with one from then on, and the other way around.
+    pub fn key_partition(
+        mut self,
+        partition: impl Fn(&str) -> (&str, &str) + Send + Sync + 'static,
+    ) -> StoreBuilder {
+        self.key_partition = Some(Arc::new(partition));
+        self
+    }
+
+    /// Keep a version number for every key, starting at 1 and bumped on every
+    /// write, for optimistic locking with `Store::update_with_version`.
+    /// Writes then serialize on a per-key lock.
+    ///
+    /// This is recorded when the store is created: it can't be turned on
+    /// later, and stores created with it are always opened with it.
+    pub fn versioned(mut self, versioned: bool) -> StoreBuilder {
+        self.versioned = versioned;
+        self
+    }
+
+    /// Allow `Store::snapshot_keys`, for a list of keys as of one point in
+    /// time. Every write then takes a shared lock on the whole store, which
+    /// the snapshot takes exclusively, so writes have a (small) cost of
+    /// their own and wait for snapshots to finish.
+    ///
+    /// This is recorded when the store is created, so every writer takes
+    /// part: it can't be turned on later, and stores created with it are
+    /// always opened with it.
+    pub fn snapshots(mut self, snapshots: bool) -> StoreBuilder {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Fail fast on signs of corruption: scan the store's directory tree when
+    /// it's opened, checking it has the expected shape and no temporary
+    /// files left behind by interrupted writes, and fail with
+    /// `FskvError::CorruptStore` otherwise. This doesn't read any values,
+    /// but still lists every directory, so it's off by default. Note that
+    /// writes in progress while opening also leave temporary files around.
+    pub fn validate_on_open(mut self, validate: bool) -> StoreBuilder {
+        self.validate_on_open = validate;
+        self
+    }
+
+    /// Hold at most `max_keys` keys: writes that would create a key past
+    /// that fail with `FskvError::Full`, while those to existing keys always
+    /// go through. Rather than scanning the store on every write, its keys
+    /// are counted in a file at its root, which is set up (with a scan) the
+    /// first time a store is opened with a limit. Only stores opened with a
+    /// limit keep it up to date, and concurrent writes to the same new key
+    /// may each count it, so the count is approximate.
+    pub fn max_keys(mut self, max_keys: usize) -> StoreBuilder {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Name each key's file after the key followed by `extension` (e.g.
+    /// `.json`), for tools browsing the store's files directly that go by
+    /// their extension. Keys are still used without it.
+    ///
+    /// This is recorded when the store is created: stores created with an
+    /// extension are opened with it, and can't be opened with another one.
+    pub fn value_extension(mut self, extension: impl Into<String>) -> StoreBuilder {
+        self.value_extension = Some(extension.into());
+        self
+    }
+
+    /// Have reads of missing keys tell a plain miss, `FskvError::KeyNotFound`,
+    /// from one where not even the key's directory exists,
+    /// `FskvError::BucketNotFound`. Directories are only removed by
+    /// `delete_where`, so on a store that's been in use for a while the
+    /// latter is rare, and many of them may point at a store opened with
+    /// the wrong settings.
+    pub fn strict_not_found(mut self, strict: bool) -> StoreBuilder {
+        self.strict_not_found = strict;
+        self
+    }
+
+    /// Have reads treat keys whose value is empty as if they didn't exist,
+    /// e.g. where an empty value is written to mean "deleted": `get` and
+    /// the rest fail with `FskvError::KeyNotFound` and `contains_key` says
+    /// they're not there. Their (empty) files are still there, and still
+    /// count as keys for listing them, `max_keys` and `put`.
+    pub fn empty_is_absent(mut self, empty_is_absent: bool) -> StoreBuilder {
+        self.empty_is_absent = empty_is_absent;
+        self
+    }
+
+    /// Record when every key is first put, which updates leave alone (unlike
+    /// its file's modification time), for `Store::created_at`. That takes
+    /// one more file next to every key put while this is on.
+    pub fn creation_times(mut self, creation_times: bool) -> StoreBuilder {
+        self.creation_times = creation_times;
+        self
+    }
+
+    /// Hash `salt` along with every key to pick its directories, so the
+    /// same key lands in different places in stores with different salts,
+    /// and it's harder to guess where a key goes by its name alone. The
+    /// hashed part of partitioned keys is salted, their shard isn't.
+    ///
+    /// This is recorded when the store is created (in the clear; it isn't
+    /// a secret from anyone who can read the store): stores created with a
+    /// salt are opened with it, and can't be opened with another one.
+    pub fn key_salt(mut self, salt: impl Into<String>) -> StoreBuilder {
+        self.key_salt = Some(salt.into());
+        self
+    }
+
+    /// Spread keys over `buckets` buckets placed on a consistent-hash ring,
+    /// rather than over the whole tree of directories MD5 hashes name:
+    /// growing the number of buckets then only moves about one key in as
+    /// many buckets as there are to the new ones, where changing the tree
+    /// moves them all. There can be up to 4096 buckets.
+    ///
+    /// The ring is built every time the store is opened, hashing 64 points
+    /// per bucket and keeping them in memory, about 1 KB per bucket: 4 MB
+    /// and some 256K MD5 hashes for the most buckets.
+    ///
+    /// This is recorded when the store is created: stores created with a
+    /// ring are opened with it, and can't be opened with another number of
+    /// buckets. To grow it, `Store::clone_into` a store built with more.
+    pub fn ring_buckets(mut self, buckets: u32) -> StoreBuilder {
+        self.ring_buckets = Some(buckets);
+        self
+    }
+
+    /// Start every value with a byte naming how it's compressed, so values
+    /// can be compressed one by one, with gzip, zstd or Brotli, using
+    /// `Store::put_with_codec` and `Store::update_with_codec`, or not at
+    /// all. Reads decompress them, except `Store::open_value_file`, which
+    /// gives the file as it is; compressed values can't be appended to.
+    ///
+    /// This is recorded when the store is created, and versions of the
+    /// crate from before codecs refuse to open such stores: stores created
+    /// with codecs keep them whatever they're opened with, and those
+    /// created without can't be opened with them.
+    pub fn value_codecs(mut self, value_codecs: bool) -> StoreBuilder {
+        self.value_codecs = value_codecs;
+        self
+    }
+
+    /// Hold back `update`s in memory and write only the last of those to
+    /// the same key within `window` of the first one, e.g. for keys updated
+    /// many times a second. Held back updates are written by the next
+    /// update to the key once the window is over, by any other write to
+    /// the key, and by `Store::flush`; until then reads (from any process)
+    /// still see the previous value, and a crash loses them. There's no
+    /// background flushing, so remember to `flush` before exiting.
+    pub fn coalesce_updates(mut self, window: Duration) -> StoreBuilder {
+        self.coalesce_window = Some(window);
+        self
+    }
+
+    /// Create value files with permissions `mode` (less the process's
+    /// umask) instead of the default; e.g. `0o600` to keep them private.
+    #[cfg(unix)]
+    pub fn file_mode(mut self, mode: u32) -> StoreBuilder {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Create the directories keys are kept in with permissions `mode`
+    /// (less the process's umask), independently of `file_mode`; e.g.
+    /// `0o700`. The root directory and existing directories are left as
+    /// they are.
+    #[cfg(unix)]
+    pub fn dir_mode(mut self, mode: u32) -> StoreBuilder {
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    /// Record how long each `get`, `put`, `update` and `delete` takes, for
+    /// `Store::latency_percentiles`. Off by default, as it takes a lock on
+    /// every such operation.
+    pub fn record_latencies(mut self, record: bool) -> StoreBuilder {
+        self.record_latencies = record;
+        self
+    }
+
+    /// Have `Store::get_many_parallel` and `Store::par_for_each` keep at
+    /// most `max` values open at once (at least one), across all the calls
+    /// to them on this store and its clones, with the rest waiting their
+    /// turn; so bulk reads don't run out of file descriptors however many
+    /// threads they use.
+    pub fn max_open_files(mut self, max: usize) -> StoreBuilder {
+        self.max_open_files = Some(max);
+        self
+    }
+
+    /// Have `update` check that what's at the key's path once its new value
+    /// is renamed into place is a regular file, failing with
+    /// `FskvError::CorruptStore` otherwise, e.g. if something else replaced
+    /// it with a directory meanwhile. That's one more `stat` per update, so
+    /// it's off by default; a directory that's already there when updating
+    /// is always reported that way.
+    pub fn verify_renames(mut self, verify: bool) -> StoreBuilder {
+        self.verify_renames = verify;
+        self
+    }
+
+    /// Have `Store::get_checked` read a value up to `retries` more times
+    /// when it doesn't match its checksum, e.g. on storage prone to
+    /// transient read errors, before failing. None by default.
+    pub fn checksum_retries(mut self, retries: usize) -> StoreBuilder {
+        self.checksum_retries = retries;
+        self
+    }
+
+    /// Have `Store::update` and the rest of the writes that replace values
+    /// try again, up to `retries` more times, when moving the new value
+    /// into place fails in a way that may be down to another process
+    /// getting in the way, e.g. one that has the file open on Windows.
+    /// They wait a millisecond before the first retry, and twice as long
+    /// before each of the next ones, up to a tenth of a second. Puts of
+    /// new keys are never retried: a key that exists stays there. None by
+    /// default.
+    pub fn update_retries(mut self, retries: usize) -> StoreBuilder {
+        self.update_retries = retries;
+        self
+    }
+
+    /// Checksum the values `Store::put_reader_checked` writes with
+    /// `algorithm` instead of CRC32.
+    ///
+    /// This is recorded when the store is created: stores created with an
+    /// algorithm are opened with it, and can't be opened with another one.
+    pub fn checksum_algorithm(mut self, algorithm: ChecksumAlgorithm) -> StoreBuilder {
+        self.checksum_algorithm = Some(algorithm);
+        self
+    }
+
+    /// End each record `Store::append_record` appends with `delimiter`
+    /// rather than a newline, e.g. for records that may span lines. It
+    /// can't be empty.
+    pub fn record_delimiter(mut self, delimiter: impl Into<String>) -> StoreBuilder {
+        self.record_delimiter = Some(delimiter.into());
+        self
+    }
+
+    /// Read and write values through `backend` instead of straight on the
+    /// local file system, e.g. to inject failures in tests.
+    pub fn backend(mut self, backend: impl Backend + 'static) -> StoreBuilder {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    pub fn build(self) -> Result<Store, FskvError> {
+        if let Some(extension) = &self.value_extension {
+            if extension.contains(['/', '\\', '\0']) {
+                return Err(FskvError::from(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "value extension {:?} contains a path separator or NUL",
+                        extension
+                    ),
+                )));
+            }
+        }
+        if let Some(buckets) = self.ring_buckets {
+            if buckets == 0 || buckets > MAX_RING_BUCKETS {
+                return Err(FskvError::from(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "ring must have between 1 and {} buckets, not {}",
+                        MAX_RING_BUCKETS, buckets
+                    ),
+                )));
+            }
+        }
+        if self.record_delimiter.as_deref() == Some("") {
+            return Err(FskvError::from(Error::new(
+                ErrorKind::InvalidInput,
+                "record delimiter is empty",
+            )));
+        }
+        // the marker has one setting per line
+        if let Some(salt) = &self.key_salt {
+            if salt.contains(['\n', '\r']) {
+                return Err(FskvError::from(Error::new(
+                    ErrorKind::InvalidInput,
+                    "key salt contains a line break",
+                )));
+            }
+        }
+        if self.create {
+            fs::create_dir_all(&self.root_directory)?;
+        }
+        // an absolute path keeps working if the process changes its
+        // working directory later on
+        let root = fs::canonicalize(&self.root_directory)?;
+
+        let marker = match Marker::read(&root)? {
+            Some(marker) => marker,
+            // a brand new store: record how it's laid out
+            None if fs::read_dir(&root)?.next().is_none() => {
+                let marker = self.marker();
+                marker.write(&root)?;
+                marker
+            }
+            // a store from before markers existed, which can only have
+            // the default settings
+            None => Marker::default(),
+        };
+        marker.check_format_version()?;
+        self.check_marker(&marker)?;
+        if self.validate_on_open {
+            validate(&root)?;
+        }
+        let ring = match marker.get("ring_buckets").map(str::parse) {
+            Some(Ok(buckets)) if buckets > 0 && buckets <= MAX_RING_BUCKETS => {
+                Some(Arc::new(Ring::new(buckets)))
+            }
+            Some(_) => {
+                return Err(FskvError::from(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "malformed ring buckets in store marker: {:?}",
+                        marker.get("ring_buckets")
+                    ),
+                )))
+            }
+            None => None,
+        };
+        let checksum_algorithm = marker.get("checksum_algorithm").unwrap_or("crc32");
+        let checksum_algorithm =
+            ChecksumAlgorithm::from_name(checksum_algorithm).ok_or_else(|| {
+                FskvError::from(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "unknown checksum algorithm in store marker: {:?}",
+                        checksum_algorithm
+                    ),
+                ))
+            })?;
+
+        let store = Store {
+            root_directory: root,
+            key_validator: self.key_validator,
+            auto_decompress: self.auto_decompress,
+            trim_trailing_newlines: self.trim_trailing_newlines,
+            temp_dir: self.temp_dir,
+            key_partition: self.key_partition,
+            versioned: marker.get("versioned") == Some("true"),
+            snapshots: marker.get("snapshots") == Some("true"),
+            max_keys: self.max_keys,
+            strict_not_found: self.strict_not_found,
+            empty_is_absent: self.empty_is_absent,
+            creation_times: self.creation_times,
+            key_salt: marker.get("key_salt").unwrap_or("").to_string(),
+            ring,
+            value_codecs: marker.get("value_codecs") == Some("true"),
+            coalescer: self
+                .coalesce_window
+                .map(|window| Arc::new(Coalescer::new(window))),
+            value_extension: marker.get("value_extension").unwrap_or("").to_string(),
+            file_mode: self.file_mode,
+            dir_mode: self.dir_mode,
+            latencies: match self.record_latencies {
+                true => Some(Arc::new(Latencies::new())),
+                false => None,
+            },
+            file_limit: self.max_open_files.map(|max| Arc::new(FileLimit::new(max))),
+            verify_renames: self.verify_renames,
+            checksum_retries: self.checksum_retries,
+            update_retries: self.update_retries,
+            checksum_algorithm,
+            record_delimiter: self.record_delimiter.unwrap_or_else(|| "\n".to_string()),
+            backend: self.backend.unwrap_or_else(|| Arc::new(FsBackend)),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        };
+        if store.max_keys.is_some() {
+            store.init_count()?;
+        }
+        Ok(store)
+    }
+
+    // the settings a new store is created with
+    fn marker(&self) -> Marker {
+        let mut marker = Marker::new();
+        if self.key_partition.is_some() {
+            marker.set("key_partition", "true");
+        }
+        if self.versioned {
+            marker.set("versioned", "true");
+        }
+        if self.snapshots {
+            marker.set("snapshots", "true");
+        }
+        match self.value_extension.as_deref() {
+            Some("") | None => (),
+            Some(extension) => marker.set("value_extension", extension),
+        }
+        match self.key_salt.as_deref() {
+            Some("") | None => (),
+            Some(salt) => marker.set("key_salt", salt),
+        }
+        if let Some(buckets) = self.ring_buckets {
+            marker.set("ring_buckets", &buckets.to_string());
+        }
+        // older versions of the crate would take the header for part of
+        // every value
+        if self.value_codecs {
+            marker.set("value_codecs", "true");
+            marker.set("format_version", &VALUE_CODECS_FORMAT_VERSION.to_string());
+        }
+        match self.checksum_algorithm {
+            Some(ChecksumAlgorithm::Crc32) | None => (),
+            Some(algorithm) => marker.set("checksum_algorithm", algorithm.name()),
+        }
+        marker
+    }
+
+    // make sure opening an existing store with this configuration still
+    // finds its keys where they are
+    fn check_marker(&self, marker: &Marker) -> Result<(), FskvError> {
+        match (marker.get("key_partition"), &self.key_partition) {
+            (Some("true"), None) => Err(FskvError::ConfigMismatch(
+                "store was created with a key partition function".to_string(),
+            )),
+            (None, Some(_)) => Err(FskvError::ConfigMismatch(
+                "store was created without a key partition function".to_string(),
+            )),
+            // stores that track versions do so whatever they're opened with
+            _ if self.versioned && marker.get("versioned").is_none() => Err(
+                FskvError::ConfigMismatch("store was created without versioning".to_string()),
+            ),
+            _ if self.snapshots && marker.get("snapshots").is_none() => Err(
+                FskvError::ConfigMismatch("store was created without snapshots".to_string()),
+            ),
+            _ if self.value_codecs && marker.get("value_codecs").is_none() => Err(
+                FskvError::ConfigMismatch("store was created without value codecs".to_string()),
+            ),
+            // as are stores whose files have an extension, but not
+            // with a different one
+            _ if self.value_extension.as_deref().is_some_and(|extension| {
+                extension != marker.get("value_extension").unwrap_or("")
+            }) =>
+            {
+                Err(FskvError::ConfigMismatch(
+                    match marker.get("value_extension") {
+                        Some(extension) => {
+                            format!("store was created with value extension {:?}", extension)
+                        }
+                        None => "store was created without a value extension".to_string(),
+                    },
+                ))
+            }
+            _ if self
+                .key_salt
+                .as_deref()
+                .is_some_and(|salt| salt != marker.get("key_salt").unwrap_or("")) =>
+            {
+                Err(FskvError::ConfigMismatch(
+                    "store was created with a different key salt".to_string(),
+                ))
+            }
+            _ if self.ring_buckets.is_some_and(|buckets| {
+                Some(buckets.to_string().as_str()) != marker.get("ring_buckets")
+            }) =>
+            {
+                Err(FskvError::ConfigMismatch(
+                    match marker.get("ring_buckets") {
+                        Some(buckets) => format!("store was created with {} ring buckets", buckets),
+                        None => "store was created without a ring".to_string(),
+                    },
+                ))
+            }
+            _ if self.checksum_algorithm.is_some_and(|algorithm| {
+                algorithm.name() != marker.get("checksum_algorithm").unwrap_or("crc32")
+            }) =>
+            {
+                Err(FskvError::ConfigMismatch(format!(
+                    "store was created with checksum algorithm {}",
+                    marker.get("checksum_algorithm").unwrap_or("crc32")
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{temp_file_name, write_atomically, FskvError, Store};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::time::SystemTime;
+use xxhash_rust::xxh64::Xxh64;
+
+// the kinds of sidecar holding the checksum of a value written with
+// `put_reader_checked`, named after the algorithm; a store only uses one
+pub(crate) const CHECKSUM_SIDECARS: [&str; 3] = ["crc32", "sha256", "xxh64"];
+
+/// How `Store::put_reader_checked` checksums values, for
+/// `StoreBuilder::checksum_algorithm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC32, which is quick, and enough to catch accidental corruption
+    #[default]
+    Crc32,
+    /// SHA-256, much slower but also much harder to fool
+    Sha256,
+    /// 64-bit xxHash, quicker than CRC32 and with fewer collisions
+    XxHash64,
+}
+
+impl ChecksumAlgorithm {
+    // what it's recorded as in the store marker, and the kind of its sidecars
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => CHECKSUM_SIDECARS[0],
+            ChecksumAlgorithm::Sha256 => CHECKSUM_SIDECARS[1],
+            ChecksumAlgorithm::XxHash64 => CHECKSUM_SIDECARS[2],
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<ChecksumAlgorithm> {
+        [
+            ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::XxHash64,
+        ]
+        .iter()
+        .copied()
+        .find(|algorithm| algorithm.name() == name)
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::XxHash64 => Hasher::XxHash64(Xxh64::new(0)),
+        }
+    }
+}
+
+/// How much space collapsing identical values would save, as found by
+/// `Store::dedup_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// How many values there are, one per key
+    pub total_values: usize,
+    /// How many distinct values there are among them
+    pub unique_values: usize,
+    /// The bytes taken by all the copies of values after the first
+    pub saveable_bytes: u64,
+}
+
+/// How a key differs between two stores, as found by `Store::diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diff {
+    /// The key is only in the store `diff` was called on
+    OnlyInSelf(String),
+    /// The key is only in the other store
+    OnlyInOther(String),
+    /// The key is in both, with different values
+    Changed(String),
+}
+
+impl Store {
+    /// The value of `key`, as `get` returns it, along with its CRC32, for
+    /// clients that pass it on to verify the value end to end. That's the
+    /// checksum stored by `put_reader_checked` in stores using CRC32s, so a
+    /// value corrupted on disk since doesn't match it, or else one computed
+    /// on the fly. Either is of the value as it was written, which is what
+    /// `get` returns unless the store trims or decompresses values.
+    pub fn get_with_checksum(&self, key: &str) -> Result<(String, u32), FskvError> {
+        self.check_key(key)?;
+        let file = self.open_value(key)?;
+        let metadata = file.metadata()?;
+        let mut bytes = Vec::new();
+        self.value_reader(key, file)?.read_to_end(&mut bytes)?;
+
+        let stored = fs::read_to_string(self.sidecar_path(key, ChecksumAlgorithm::Crc32.name()))
+            .ok()
+            .and_then(|stored| parse_checksum(&stored, &metadata))
+            .and_then(|checksum| u32::from_str_radix(&checksum, 16).ok());
+        let checksum = stored.unwrap_or_else(|| crc32fast::hash(&bytes));
+        Ok((self.decode_value(bytes)?, checksum))
+    }
+
+    /// A hash of all the keys and values in the store, the same for stores
+    /// holding the same data whatever order their files are listed in, e.g.
+    /// to tell whether anything changed between deploys. This reads every
+    /// value, so it takes a while on large stores.
+    pub fn fingerprint(&self) -> Result<String, FskvError> {
+        let mut keys = self.keys().collect::<Result<Vec<_>, _>>()?;
+        keys.sort();
+
+        let mut context = md5::Context::new();
+        for key in keys {
+            let checksum = match self.get_bytes(&key) {
+                Ok(value) => crc32fast::hash(&value),
+                // deleted since it was listed, or empty and taken as absent
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+            // keys can't hold a NUL, so this can't be ambiguous
+            context.consume(format!("{}\0{:08x}\n", key, checksum));
+        }
+        Ok(format!("{:x}", context.compute()))
+    }
+
+    /// Every key along with the size and CRC32 of its value, in key order;
+    /// e.g. for a replica to compare against its own and fetch only the
+    /// keys that differ. This reads every value, like `fingerprint`.
+    pub fn manifest(&self) -> Result<Vec<(String, u64, u32)>, FskvError> {
+        let mut manifest = Vec::new();
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            let value = match fs::read(path) {
+                Ok(value) => value,
+                // deleted since it was listed
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(FskvError::from(e)),
+            };
+            manifest.push((key, value.len() as u64, crc32fast::hash(&value)));
+        }
+
+        manifest.sort();
+        Ok(manifest)
+    }
+
+    /// The keys that differ between this store and `other`, in key order,
+    /// going by their `manifest`s (so values count as changed when their
+    /// sizes or CRC32s differ); e.g. to show what changed between two
+    /// copies of a store. Both manifests are taken up front, which reads
+    /// every value of both stores.
+    pub fn diff(
+        &self,
+        other: &Store,
+    ) -> Result<impl Iterator<Item = Result<Diff, FskvError>>, FskvError> {
+        let mine = self.manifest()?;
+        let theirs = other.manifest()?;
+
+        // both are sorted by key, so walk them side by side
+        let (mut mine, mut theirs) = (mine.into_iter().peekable(), theirs.into_iter().peekable());
+        Ok(iter::from_fn(move || loop {
+            let diff = match (mine.peek(), theirs.peek()) {
+                (Some(m), Some(t)) if m.0 < t.0 => Diff::OnlyInSelf(mine.next()?.0),
+                (Some(m), Some(t)) if m.0 > t.0 => Diff::OnlyInOther(theirs.next()?.0),
+                (Some(_), Some(_)) => {
+                    let (m, t) = (mine.next()?, theirs.next()?);
+                    if m == t {
+                        continue;
+                    }
+                    Diff::Changed(m.0)
+                }
+                (Some(_), None) => Diff::OnlyInSelf(mine.next()?.0),
+                (None, Some(_)) => Diff::OnlyInOther(theirs.next()?.0),
+                (None, None) => return None,
+            };
+            return Some(Ok(diff));
+        }))
+    }
+
+    /// How many values are duplicates of others, and how much space they
+    /// take, e.g. to decide whether storing values by content would pay
+    /// off. This reads and hashes every value in the store.
+    pub fn dedup_stats(&self) -> Result<DedupStats, FskvError> {
+        let mut stats = DedupStats::default();
+        let mut seen = HashSet::new();
+        for entry in self.key_files() {
+            let (_, path) = entry?;
+            let value = match fs::read(path) {
+                Ok(value) => value,
+                // deleted since it was listed
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(FskvError::from(e)),
+            };
+            stats.total_values += 1;
+            // values with the same digest are taken to be the same
+            if seen.insert(md5::compute(&value).0) {
+                stats.unique_values += 1;
+            } else {
+                stats.saveable_bytes += value.len() as u64;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Put `key` with the value read from `src`, streamed to disk rather
+    /// than held in memory, and return its checksum in hex, which is also
+    /// stored for `get_reader_checked` to verify; a CRC32 unless the store
+    /// was created with another `StoreBuilder::checksum_algorithm`. Like
+    /// `put`, this fails if the key exists, and the value only shows up
+    /// once it's all been written.
+    pub fn put_reader_checked(&self, key: &str, mut src: impl Read) -> Result<String, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let key_path = self.get_key_path(key);
+        self.create_bucket(&key_path)?;
+        let _lock = match self.versioned {
+            true => Some(self.lock_key(key, true)?),
+            false => None,
+        };
+
+        // stream to a file of our own, to be linked into place when done,
+        // uncompressed in stores with codecs
+        let tmp_file = key_path.join(temp_file_name());
+        let header = self.encode_value(&[], None)?;
+        let written = (|| {
+            let mut file =
+                self.in_bucket(&key_path, || self.value_file_options().open(&tmp_file))?;
+            file.write_all(&header)?;
+            let mut hasher = self.checksum_algorithm.hasher();
+            let mut buf = [0; 8192];
+            loop {
+                let n = match src.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+            }
+            Ok((hasher.finalize(), file.metadata()?))
+        })();
+        let (checksum, metadata) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                fs::remove_file(&tmp_file).unwrap_or(());
+                return Err(FskvError::from(e));
+            }
+        };
+
+        self.link_new_value(key, &tmp_file)?;
+
+        // until this is there, checked reads of the key fail
+        self.write_checksum(key, &checksum, &metadata)?;
+        self.finish_put(key)?;
+        Ok(checksum)
+    }
+
+    /// Read the value of a key written with `put_reader_checked`, checking
+    /// it against the checksum stored then: reading it all the way through
+    /// fails, at the end, if it doesn't match. Keys last written some
+    /// other way have no checksum to check against, which is an error.
+    pub fn get_reader_checked(&self, key: &str) -> Result<impl Read, FskvError> {
+        self.check_key(key)?;
+        let file = self.open_value(key)?;
+        let metadata = file.metadata()?;
+        let kind = self.checksum_algorithm.name();
+        let expected = fs::read_to_string(self.sidecar_path(key, kind))
+            .ok()
+            .and_then(|stored| parse_checksum(&stored, &metadata));
+
+        match expected {
+            Some(expected) => Ok(CheckedReader {
+                inner: self.value_reader(key, file)?,
+                hasher: self.checksum_algorithm.hasher(),
+                expected,
+                key: key.to_string(),
+            }),
+            None => Err(FskvError::InvalidValue(format!(
+                "no checksum for the value of {:?}",
+                key
+            ))),
+        }
+    }
+
+    /// Like `get`, but for keys written with `put_reader_checked`, checking
+    /// the value against its stored checksum like `get_reader_checked` does.
+    /// On a mismatch it's read again, up to `StoreBuilder::checksum_retries`
+    /// times, in case it was a glitch reading it rather than the value on
+    /// disk being corrupt.
+    pub fn get_checked(&self, key: &str) -> Result<String, FskvError> {
+        let mut retries = 0;
+        loop {
+            let mut bytes = Vec::new();
+            match self.get_reader_checked(key)?.read_to_end(&mut bytes) {
+                Ok(_) => return self.decode_value(bytes),
+                // what a checksum mismatch is reported as
+                Err(ref e)
+                    if e.kind() == ErrorKind::InvalidData && retries < self.checksum_retries =>
+                {
+                    retries += 1
+                }
+                Err(e) => return Err(FskvError::from(e)),
+            }
+        }
+    }
+
+    // store the checksum along with what identifies the file it's for,
+    // so it's not mistaken for the checksum of whatever replaces it
+    fn write_checksum(
+        &self,
+        key: &str,
+        checksum: &str,
+        metadata: &fs::Metadata,
+    ) -> Result<(), FskvError> {
+        let contents = format!("{} {} {}", checksum, metadata.len(), mtime_nanos(metadata));
+        write_atomically(
+            &self.sidecar_path(key, self.checksum_algorithm.name()),
+            contents.as_bytes(),
+        )
+        .map_err(FskvError::from)
+    }
+
+    // remove the checksums of a value that's gone, with whichever algorithm
+    // they were taken, so a later value of the key isn't checked against them
+    pub(crate) fn remove_checksums(&self, key: &str) -> Result<(), FskvError> {
+        for kind in CHECKSUM_SIDECARS.iter() {
+            self.remove_sidecar(key, kind)?;
+        }
+        Ok(())
+    }
+}
+
+// the stored checksum, if it's for the file described by `metadata`
+fn parse_checksum(stored: &str, metadata: &fs::Metadata) -> Option<String> {
+    let mut fields = stored.split_whitespace();
+    let checksum = fields.next()?.to_string();
+    let len: u64 = fields.next()?.parse().ok()?;
+    let mtime: u128 = fields.next()?.parse().ok()?;
+    match len == metadata.len() && mtime == mtime_nanos(metadata) {
+        true => Some(checksum),
+        false => None,
+    }
+}
+
+pub(crate) fn mtime_nanos(metadata: &fs::Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+// a checksum being computed with any of the algorithms
+#[derive(Clone)]
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(Sha256),
+    XxHash64(Xxh64),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::XxHash64(hasher) => hasher.update(data),
+        }
+    }
+
+    // in hex, as it's stored
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            Hasher::XxHash64(hasher) => format!("{:016x}", hasher.digest()),
+        }
+    }
+}
+
+// a value being read along with its checksum, compared once it's all read
+struct CheckedReader {
+    inner: Box<dyn Read + Send>,
+    hasher: Hasher,
+    expected: String,
+    key: String,
+}
+
+impl Read for CheckedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() && self.hasher.clone().finalize() != self.expected {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("checksum mismatch for the value of {:?}", self.key),
+            ));
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{FskvError, Store};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+impl Store {
+    /// Delete every key `pred` returns true for and return how many were
+    /// deleted; e.g. to clean up keys following some naming scheme. The
+    /// buckets left empty are removed too, along with the directories
+    /// above them that end up empty. Keys written while it runs may or may
+    /// not be looked at.
+    pub fn delete_where(&self, pred: impl Fn(&str) -> bool) -> Result<usize, FskvError> {
+        // list them first rather than delete while walking the tree
+        let mut matching = Vec::new();
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            if pred(&key) {
+                matching.push((key, path));
+            }
+        }
+
+        let mut deleted = 0;
+        let mut buckets = BTreeSet::new();
+        for (key, path) in matching {
+            match self.delete(&key) {
+                Ok(()) => deleted += 1,
+                // deleted since it was listed
+                Err(e) if e.is_not_found() => (),
+                Err(e) => return Err(e),
+            }
+            if let Some(bucket) = path.parent() {
+                buckets.insert(bucket.to_path_buf());
+            }
+        }
+        self.remove_empty_buckets(buckets);
+        Ok(deleted)
+    }
+
+    // remove the buckets that are empty, and then each of their parents
+    // that's left empty, up to the root; those that aren't are left alone
+    fn remove_empty_buckets(&self, buckets: BTreeSet<PathBuf>) {
+        for bucket in buckets {
+            let mut dir = bucket.as_path();
+            while dir != self.root_directory && fs::remove_dir(dir).is_ok() {
+                match dir.parent() {
+                    Some(parent) => dir = parent,
+                    None => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{FskvError, Store};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// updates held back to be written together, shared by all clones of a store
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    window: Duration,
+    // the latest value of each key, and when its oldest unwritten update was
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Coalescer {
+    pub(crate) fn new(window: Duration) -> Coalescer {
+        Coalescer {
+            window,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Store {
+    /// Write out every update still held back by
+    /// `StoreBuilder::coalesce_updates`, returning the first error, if any.
+    pub fn flush(&self) -> Result<(), FskvError> {
+        let coalescer = match &self.coalescer {
+            Some(coalescer) => coalescer,
+            None => return Ok(()),
+        };
+        let pending: Vec<_> = coalescer
+            .pending
+            .lock()
+            .expect("pending updates lock poisoned")
+            .drain()
+            .collect();
+
+        let mut result = Ok(());
+        for (key, (value, _)) in pending {
+            let written = self.write_update(&key, &value);
+            result = result.and(written);
+        }
+        result
+    }
+
+    // hold back an update, writing it along with any earlier ones once the
+    // oldest of them is older than the window
+    pub(crate) fn coalesce(
+        &self,
+        coalescer: &Coalescer,
+        key: &str,
+        value: &str,
+    ) -> Result<(), FskvError> {
+        let due = {
+            let mut pending = coalescer
+                .pending
+                .lock()
+                .expect("pending updates lock poisoned");
+            match pending.get_mut(key) {
+                Some((_, since)) if since.elapsed() >= coalescer.window => {
+                    pending.remove(key);
+                    true
+                }
+                Some((held, _)) => {
+                    *held = value.to_string();
+                    false
+                }
+                None => {
+                    pending.insert(key.to_string(), (value.to_string(), Instant::now()));
+                    false
+                }
+            }
+        };
+
+        match due {
+            true => self.write_update(key, value),
+            false => Ok(()),
+        }
+    }
+
+    // write out the update held back for `key`, if any, before it's
+    // changed some other way
+    pub(crate) fn flush_key(&self, key: &str) -> Result<(), FskvError> {
+        let held = match &self.coalescer {
+            Some(coalescer) => coalescer
+                .pending
+                .lock()
+                .expect("pending updates lock poisoned")
+                .remove(key),
+            None => None,
+        };
+
+        match held {
+            Some((value, _)) => self.write_update(key, &value),
+            None => Ok(()),
+        }
+    }
+}
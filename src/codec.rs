@@ -0,0 +1,143 @@
+use crate::{ContentEncoding, FskvError, Store};
+use flate2::read::GzDecoder;
+use std::borrow::Cow;
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+
+// in stores built with `StoreBuilder::value_codecs`, every value starts
+// with one byte naming how the rest of it is compressed; these are on
+// disk, so they can only ever be added to
+const NO_CODEC: u8 = 0;
+const GZIP_CODEC: u8 = 1;
+const ZSTD_CODEC: u8 = 2;
+const BROTLI_CODEC: u8 = 3;
+
+impl Store {
+    /// Like `put`, but compressing the value with `codec`, or not at all
+    /// if it's `None`; e.g. to compress values holding JSON but not those
+    /// holding images, which already are. Reading it back decompresses it.
+    /// Only stores built with `StoreBuilder::value_codecs` can keep
+    /// compressed values, others fail with `FskvError::ConfigMismatch`.
+    pub fn put_with_codec(
+        &self,
+        key: &str,
+        value: &str,
+        codec: Option<ContentEncoding>,
+    ) -> Result<(), FskvError> {
+        let stored = self.encode_value(value.as_bytes(), codec)?;
+        self.put_encoded(key, &stored)
+    }
+
+    /// Like `update`, but compressing the value with `codec`, as for
+    /// `put_with_codec`. Unlike `update`, it's never held back.
+    pub fn update_with_codec(
+        &self,
+        key: &str,
+        value: &str,
+        codec: Option<ContentEncoding>,
+    ) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let stored = self.encode_value(value.as_bytes(), codec)?;
+        let _lock = match self.versioned {
+            true => Some(self.lock_key(key, true)?),
+            false => None,
+        };
+        self.bump_version(key)?;
+        self.replace_stored(key, &stored)
+    }
+
+    // what's written for `value`: in stores with codecs, the header naming
+    // `codec` followed by the value compressed with it
+    pub(crate) fn encode_value<'a>(
+        &self,
+        value: &'a [u8],
+        codec: Option<ContentEncoding>,
+    ) -> Result<Cow<'a, [u8]>, FskvError> {
+        if !self.value_codecs {
+            return match codec {
+                None => Ok(Cow::Borrowed(value)),
+                Some(_) => Err(FskvError::ConfigMismatch(
+                    "store was created without value codecs".to_string(),
+                )),
+            };
+        }
+
+        // nothing's gained compressing an empty value, which is then
+        // always just the header
+        let mut stored = Vec::with_capacity(value.len() + 1);
+        match codec {
+            Some(codec) if !value.is_empty() => {
+                stored.push(match codec {
+                    ContentEncoding::Gzip => GZIP_CODEC,
+                    ContentEncoding::Brotli => BROTLI_CODEC,
+                    ContentEncoding::Zstd => ZSTD_CODEC,
+                });
+                stored.extend(codec.compress(value)?);
+            }
+            _ => {
+                stored.push(NO_CODEC);
+                stored.extend_from_slice(value);
+            }
+        }
+        Ok(Cow::Owned(stored))
+    }
+
+    // read the value `stored` holds as it was written, past its header and
+    // decompressed, in stores with codecs
+    pub(crate) fn value_reader<'a>(
+        &self,
+        key: &str,
+        mut stored: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>, FskvError> {
+        if !self.value_codecs {
+            return Ok(Box::new(stored));
+        }
+
+        let mut codec = [NO_CODEC];
+        // a file without even the header is an empty value
+        if stored.read(&mut codec)? == 0 {
+            return Ok(Box::new(stored));
+        }
+        match codec[0] {
+            NO_CODEC => Ok(Box::new(stored)),
+            GZIP_CODEC => Ok(Box::new(GzDecoder::new(stored))),
+            ZSTD_CODEC => Ok(Box::new(zstd::stream::read::Decoder::new(stored)?)),
+            BROTLI_CODEC => Ok(Box::new(brotli::Decompressor::new(stored, 4096))),
+            codec => Err(FskvError::CorruptStore(format!(
+                "value of {:?} has an unknown codec: {}",
+                key, codec
+            ))),
+        }
+    }
+
+    // make sure what's appended to the value of `key` ends up as part of
+    // it: after the header of a new value, and never to a compressed one
+    pub(crate) fn encode_appended<'a>(
+        &self,
+        key: &str,
+        data: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, FskvError> {
+        if !self.value_codecs {
+            return Ok(Cow::Borrowed(data));
+        }
+        let mut codec = [NO_CODEC];
+        // an empty value is there to append to even if reads take it as
+        // absent
+        let file = self
+            .backend
+            .open(&self.key_file(key), fs::OpenOptions::new().read(true));
+        match file {
+            Ok(mut file) => match file.read(&mut codec)? {
+                0 => self.encode_value(data, None),
+                _ if codec[0] == NO_CODEC => Ok(Cow::Borrowed(data)),
+                _ => Err(FskvError::InvalidValue(format!(
+                    "value of {:?} is compressed, it can't be appended to",
+                    key
+                ))),
+            },
+            Err(ref e) if e.kind() == ErrorKind::NotFound => self.encode_value(data, None),
+            Err(e) => Err(FskvError::from(e)),
+        }
+    }
+}
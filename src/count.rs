@@ -0,0 +1,152 @@
+use crate::{FskvError, Store, SINGLE_DIRECTORY_LENGTH};
+use std::fs;
+use std::io::prelude::*;
+use std::io::{ErrorKind, SeekFrom};
+
+// the file at the root of a store keeping (roughly) how many keys it holds,
+// so limiting their number doesn't take a scan of the whole store
+pub(crate) const COUNT_FILE: &str = ".fskv.len";
+
+impl Store {
+    // start counting the keys of a store that wasn't being counted yet,
+    // which takes a scan of all of them
+    pub(crate) fn init_count(&self) -> Result<(), FskvError> {
+        if self.root_directory.join(COUNT_FILE).exists() {
+            return Ok(());
+        }
+        let count = self.len()?;
+        self.update_count(|_| Ok(count))
+    }
+
+    // make room for a new key, or fail if the store is at its limit
+    pub(crate) fn reserve_key(&self) -> Result<(), FskvError> {
+        match self.max_keys {
+            Some(max) => self.update_count(|n| match n {
+                n if n >= max => Err(FskvError::Full),
+                n => Ok(n + 1),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // give back the room of a key that was removed, or never created
+    pub(crate) fn release_key(&self) -> Result<(), FskvError> {
+        match self.max_keys {
+            Some(_) => self.update_count(|n| Ok(n.saturating_sub(1))),
+            None => Ok(()),
+        }
+    }
+
+    /// How many keys there are, by listing all of them.
+    pub fn len(&self) -> Result<usize, FskvError> {
+        let mut count = 0;
+        for entry in self.key_files() {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Whether there are no keys at all.
+    pub fn is_empty(&self) -> Result<bool, FskvError> {
+        match self.key_files().next() {
+            Some(entry) => entry.map(|_| false).map_err(FskvError::from),
+            None => Ok(true),
+        }
+    }
+
+    /// How many keys there are as counted for `StoreBuilder::max_keys`,
+    /// without listing them, which may be off (see `rebuild_index`); or
+    /// `len` if the store has never been opened with a limit.
+    pub fn approx_len(&self) -> Result<usize, FskvError> {
+        match fs::read_to_string(self.root_directory.join(COUNT_FILE)) {
+            Ok(contents) => parse_count(&contents),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => self.len(),
+            Err(e) => Err(FskvError::from(e)),
+        }
+    }
+
+    /// How many levels of directories would keep the keys there are now
+    /// (see `len`) down to `target_bucket_size` per bucket on average, at
+    /// the least, assuming their hashes spread them evenly; e.g. to tell
+    /// whether the store has outgrown its layout. Stores are always laid
+    /// out three levels deep; partitioned keys use one of them for their
+    /// shard, which this doesn't take into account.
+    pub fn suggest_depth(&self, target_bucket_size: usize) -> Result<usize, FskvError> {
+        if target_bucket_size == 0 {
+            return Err(FskvError::InvalidValue(
+                "target bucket size must be at least 1".to_string(),
+            ));
+        }
+        Ok(depth_for(self.len()?, target_bucket_size))
+    }
+
+    /// Count the keys again, by listing all of them, and set the count kept
+    /// for `StoreBuilder::max_keys` to that; e.g. when it's drifted after
+    /// crashes or changes made behind the store's back, or the file it's
+    /// kept in got corrupt. Writes that count new keys wait for it to be
+    /// done. Stores that don't keep a count are left as they are.
+    pub fn rebuild_index(&self) -> Result<(), FskvError> {
+        if self.max_keys.is_none() && !self.root_directory.join(COUNT_FILE).exists() {
+            return Ok(());
+        }
+        let mut file = self.lock_count()?;
+        let count = self.len()?;
+        write_count(&mut file, count)
+    }
+
+    // read-modify-write the count, with it locked against other writers
+    fn update_count(
+        &self,
+        f: impl FnOnce(usize) -> Result<usize, FskvError>,
+    ) -> Result<(), FskvError> {
+        let mut file = self.lock_count()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let count = f(parse_count(&contents)?)?;
+        write_count(&mut file, count)
+    }
+
+    // the file holding the count, locked against other writers until it's
+    // dropped
+    fn lock_count(&self) -> Result<fs::File, FskvError> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root_directory.join(COUNT_FILE))?;
+        file.lock()?;
+        Ok(file)
+    }
+}
+
+// the fewest levels (at least one) of buckets that hold `keys` with no
+// more than `target` per bucket on average; every level multiplies the
+// number of buckets by the number of names a directory can have
+pub(crate) fn depth_for(keys: usize, target: usize) -> usize {
+    let fanout = 16u128.pow(SINGLE_DIRECTORY_LENGTH as u32);
+    let (mut depth, mut buckets) = (1, fanout);
+    while (keys as u128) > buckets * target as u128 {
+        depth += 1;
+        buckets *= fanout;
+    }
+    depth
+}
+
+fn parse_count(contents: &str) -> Result<usize, FskvError> {
+    // an empty file is a count that was never written
+    match contents.trim() {
+        "" => Ok(0),
+        n => n
+            .parse()
+            .map_err(|_| FskvError::CorruptStore(format!("malformed key count: {:?}", contents))),
+    }
+}
+
+fn write_count(file: &mut fs::File, count: usize) -> Result<(), FskvError> {
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(count.to_string().as_bytes())?;
+    Ok(())
+}
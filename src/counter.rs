@@ -0,0 +1,59 @@
+use crate::{FskvError, Store};
+
+// counters are keys holding an integer as text, written under the key's
+// lock so concurrent changes don't get lost
+impl Store {
+    /// Add `by` (which may be negative) to the integer stored at `key`,
+    /// creating it at 0 if needed, and return its new value.
+    pub fn increment(&self, key: &str, by: i64) -> Result<i64, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let current = match self.get(key) {
+            Ok(value) => parse_counter(key, &value)?,
+            Err(e) if e.is_not_found() => 0,
+            Err(e) => return Err(e),
+        };
+        let new = current
+            .checked_add(by)
+            .ok_or_else(|| FskvError::InvalidValue(format!("counter {:?} would overflow", key)))?;
+
+        self.write_locked(key, &new.to_string()).map(|_| new)
+    }
+
+    /// Add one to the counter at `key` (creating it at 0 if needed) if it's
+    /// below `limit`, returning whether it did; e.g. for a quota of `limit`
+    /// uses, each taken by a call returning `true`.
+    pub fn update_if_below(&self, key: &str, limit: i64) -> Result<bool, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let current = match self.get(key) {
+            Ok(value) => parse_counter(key, &value)?,
+            Err(e) if e.is_not_found() => 0,
+            Err(e) => return Err(e),
+        };
+        if current >= limit {
+            return Ok(false);
+        }
+
+        // below the limit, so it can't overflow
+        self.write_locked(key, &(current + 1).to_string())
+            .map(|_| true)
+    }
+
+    /// Set the counter at `key` to `to`, e.g. at the start of a new period.
+    /// It takes the same lock as `increment`, so no increment is lost by
+    /// running concurrently with a reset: each one lands either before it
+    /// or after it.
+    pub fn reset_counter(&self, key: &str, to: i64) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        self.write_locked(key, &to.to_string())
+    }
+}
+
+fn parse_counter(key: &str, value: &str) -> Result<i64, FskvError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| FskvError::InvalidValue(format!("value of {:?} isn't an integer", key)))
+}
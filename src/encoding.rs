@@ -0,0 +1,49 @@
+use crate::{FskvError, Store};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// What `Store::get_encoded` turns values into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// As `get` returns them
+    Utf8,
+    /// UTF-16, little-endian, without a byte order mark
+    Utf16Le,
+    /// UTF-16, big-endian, without a byte order mark
+    Utf16Be,
+    /// The standard base64 alphabet, padded, of the UTF-8 bytes
+    Base64,
+}
+
+impl Store {
+    /// The value of `key`, as `get` returns it, encoded as `encoding`; e.g.
+    /// for serving clients that want it in another form than it's stored.
+    pub fn get_encoded(&self, key: &str, encoding: Encoding) -> Result<Vec<u8>, FskvError> {
+        let value = self.get(key)?;
+        Ok(match encoding {
+            Encoding::Utf8 => value.into_bytes(),
+            Encoding::Utf16Le => value.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => value.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Base64 => base64(value.as_bytes()),
+        })
+    }
+}
+
+fn base64(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        // up to 24 bits, 6 at a time
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => BASE64_ALPHABET[((bits >> (18 - 6 * i)) & 0x3f) as usize],
+                false => b'=',
+            });
+        }
+    }
+    encoded
+}
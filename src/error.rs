@@ -0,0 +1,101 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum FskvError {
+    /// An error from the underlying file system
+    Io(io::Error),
+    /// There's no such key in the store
+    KeyNotFound(String),
+    /// There's no such key, nor even the directory it would be in, which
+    /// may mean the store is laid out differently than it's opened as;
+    /// only in stores opened with `StoreBuilder::strict_not_found`
+    BucketNotFound(String),
+    /// A key that's only written if it's new already exists
+    KeyExists(String),
+    /// The key was rejected, either by the built-in checks or by the
+    /// store's validator, with the reason why
+    InvalidKey(String),
+    /// The store was opened with settings that don't match the ones it was
+    /// created with
+    ConfigMismatch(String),
+    /// Something on disk isn't laid out the way the store expects
+    CorruptStore(String),
+    /// The store was written in a newer format than this version of the
+    /// crate supports
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The stored value can't be used for the requested operation, e.g. it
+    /// isn't the JSON it's expected to be
+    InvalidValue(String),
+    /// The store already holds as many keys as it's allowed to
+    Full,
+    /// A versioned write expected the key to be at a version it isn't
+    VersionMismatch { expected: u64, actual: u64 },
+    /// The deadline for reading the key passed before it could be read
+    Timeout(String),
+}
+
+impl FskvError {
+    /// Whether this is a read of a key that doesn't exist, whichever of the
+    /// errors for that it is
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            FskvError::KeyNotFound(_) | FskvError::BucketNotFound(_)
+        )
+    }
+
+    /// The kind of the file system error this is, if it is one; e.g. to
+    /// tell a write that was interrupted from one that wasn't allowed.
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            FskvError::Io(e) => Some(e.kind()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FskvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FskvError::Io(e) => write!(f, "{}", e),
+            FskvError::KeyNotFound(key) => write!(f, "key not found: {:?}", key),
+            FskvError::BucketNotFound(key) => {
+                write!(f, "key not found, nor its directory: {:?}", key)
+            }
+            FskvError::KeyExists(key) => write!(f, "key already exists: {:?}", key),
+            FskvError::InvalidKey(reason) => write!(f, "invalid key: {}", reason),
+            FskvError::ConfigMismatch(reason) => write!(f, "configuration mismatch: {}", reason),
+            FskvError::CorruptStore(reason) => write!(f, "corrupt store: {}", reason),
+            FskvError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "unsupported store format version {} (up to {} is supported)",
+                found, supported
+            ),
+            FskvError::InvalidValue(reason) => write!(f, "invalid value: {}", reason),
+            FskvError::Full => write!(f, "store is full"),
+            FskvError::VersionMismatch { expected, actual } => write!(
+                f,
+                "version mismatch: expected {} but found {}",
+                expected, actual
+            ),
+            FskvError::Timeout(key) => write!(f, "deadline exceeded reading key: {:?}", key),
+        }
+    }
+}
+
+impl Error for FskvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FskvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FskvError {
+    fn from(e: io::Error) -> FskvError {
+        FskvError::Io(e)
+    }
+}
@@ -0,0 +1,45 @@
+use crate::Store;
+use std::sync::mpsc;
+
+/// What a successful write did to a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    Put,
+    Update,
+    Delete,
+}
+
+/// Sent to subscribers after every successful write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MutationEvent {
+    pub key: String,
+    pub kind: MutationKind,
+}
+
+impl Store {
+    /// Receive an event for every successful write made through this store
+    /// (or its clones) from now on. Every subscriber gets every event, and
+    /// dropping the receiver simply unsubscribes it.
+    pub fn subscribe(&self) -> mpsc::Receiver<MutationEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .expect("subscribers lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    pub(crate) fn notify(&self, key: &str, kind: MutationKind) {
+        let mut subscribers = self.subscribers.lock().expect("subscribers lock poisoned");
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = MutationEvent {
+            key: key.to_string(),
+            kind,
+        };
+        // the channels are unbounded, so this never blocks; it only fails
+        // once the receiver is gone, and then there's no one to tell
+        subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{FskvError, Store, StoreBuilder};
+use std::fs;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::time::SystemTime;
+
+impl Store {
+    /// Write every entry as a tar stream: each file in the archive is named
+    /// after the key and holds its value, so the result can be inspected
+    /// with standard tools and loaded back with `import_tar`.
+    pub fn export_tar(&self, writer: impl Write) -> Result<(), FskvError> {
+        self.export_tar_using(writer, &|_| true, &mut |_| ())
+            .map(|_| ())
+    }
+
+    /// Like `export_tar`, but only for the keys `pred` holds for (e.g. to
+    /// back up only `critical:` keys), returning how many were exported.
+    pub fn export_where(
+        &self,
+        writer: impl Write,
+        pred: impl Fn(&str) -> bool,
+    ) -> Result<usize, FskvError> {
+        self.export_tar_using(writer, &pred, &mut |_| ())
+    }
+
+    /// Like `export_tar`, but calling `progress` with the number of keys
+    /// exported so far and the total, first with none done and then after
+    /// every key, e.g. to show a progress bar while exporting a large
+    /// store. Getting the total takes an extra pass over the keys (but not
+    /// the values); keys written meanwhile may make the count go past it.
+    pub fn export_with_progress(
+        &self,
+        writer: impl Write,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), FskvError> {
+        let mut total = 0;
+        for entry in self.key_files() {
+            entry?;
+            total += 1;
+        }
+
+        progress(0, total);
+        self.export_tar_using(writer, &|_| true, &mut |done| progress(done, total))
+            .map(|_| ())
+    }
+
+    // `export_tar` of the keys matching `pred`, calling `exported` with the
+    // number of keys written after each one and returning the total
+    fn export_tar_using(
+        &self,
+        writer: impl Write,
+        pred: &dyn Fn(&str) -> bool,
+        exported: &mut dyn FnMut(usize),
+    ) -> Result<usize, FskvError> {
+        let mut archive = tar::Builder::new(writer);
+        let mut done = 0;
+
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            if !pred(&key) {
+                continue;
+            }
+            let file = fs::File::open(&path)?;
+            let metadata = file.metadata()?;
+            let mtime = metadata
+                .modified()?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            if self.value_codecs {
+                // how long the value is is only known once it's been
+                // decompressed
+                let mut value = Vec::new();
+                self.value_reader(&key, file)?.read_to_end(&mut value)?;
+                header.set_size(value.len() as u64);
+                archive.append_data(&mut header, &key, &value[..])?;
+            } else {
+                header.set_size(metadata.len());
+                // stream the value straight from disk
+                archive.append_data(&mut header, &key, file)?;
+            }
+            done += 1;
+            exported(done);
+        }
+
+        archive.into_inner().and_then(|mut w| w.flush())?;
+        Ok(done)
+    }
+
+    /// Create a store as configured by `builder` and copy every key and
+    /// value to it, leaving this one as it is; e.g. for changes to settings
+    /// that can't be made to an existing store, like its key salt. Like
+    /// `put`, this fails on the first key the new store already has.
+    pub fn clone_into(&self, builder: StoreBuilder) -> Result<Store, FskvError> {
+        let clone = builder.create(true).build()?;
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            let mut stored = fs::read(path)?;
+            // values are compressed as they were only if the clone keeps
+            // codecs as well
+            if clone.value_codecs != self.value_codecs {
+                let mut value = Vec::new();
+                self.value_reader(&key, &stored[..])?
+                    .read_to_end(&mut value)?;
+                stored = clone.encode_value(&value, None)?.into_owned();
+            }
+            clone.put_encoded(&key, &stored)?;
+        }
+        Ok(clone)
+    }
+
+    /// Copy every key of `other` into this store: the keys this one doesn't
+    /// have are written as they are, and for those it does the value
+    /// written is `resolve(key, mine, theirs)`; returns how many keys were
+    /// written. Each key is locked here from reading it to writing it, but
+    /// the merge as a whole isn't atomic.
+    pub fn merge_from(
+        &self,
+        other: &Store,
+        resolve: impl Fn(&str, &str, &str) -> String,
+    ) -> Result<usize, FskvError> {
+        let mut merged = 0;
+        for key in other.keys() {
+            let key = key?;
+            let theirs = match other.get(&key) {
+                Ok(theirs) => theirs,
+                // deleted since it was listed
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+
+            let _writing = self.check_key_for_write(&key)?;
+            let _lock = self.lock_key(&key, true)?;
+            let value = match self.get(&key) {
+                Ok(mine) => resolve(&key, &mine, &theirs),
+                Err(e) if e.is_not_found() => theirs,
+                Err(e) => return Err(e),
+            };
+            self.write_locked(&key, &value)?;
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    /// Restore the entries of a tar stream produced by `export_tar`. Like
+    /// `put`, this fails on the first key that already exists.
+    pub fn import_tar(&self, reader: impl Read) -> Result<(), FskvError> {
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let key = String::from_utf8(entry.path_bytes().into_owned())
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            let mut value = String::new();
+            entry.read_to_string(&mut value)?;
+            self.put(&key, &value)?;
+        }
+
+        Ok(())
+    }
+}
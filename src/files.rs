@@ -0,0 +1,50 @@
+use crate::Store;
+use std::sync::{Condvar, Mutex};
+
+// how many files parallel operations may have open at once, shared by all
+// clones of a store
+#[derive(Debug)]
+pub(crate) struct FileLimit {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl FileLimit {
+    pub(crate) fn new(max: usize) -> FileLimit {
+        FileLimit {
+            // there's no making progress with none
+            max: max.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+}
+
+// one of the files allowed open, given back when dropped
+pub(crate) struct FilePermit<'a> {
+    limit: &'a FileLimit,
+}
+
+impl Drop for FilePermit<'_> {
+    fn drop(&mut self) {
+        let mut open = self.limit.open.lock().expect("file limit lock poisoned");
+        *open -= 1;
+        self.limit.closed.notify_one();
+    }
+}
+
+impl Store {
+    // wait until opening another file keeps within the limit, if there's
+    // one, and count it as open until the returned guard is dropped
+    pub(crate) fn file_permit(&self) -> Option<FilePermit<'_>> {
+        self.file_limit.as_ref().map(|limit| {
+            let mut open = limit.open.lock().expect("file limit lock poisoned");
+            while *open >= limit.max {
+                open = limit.closed.wait(open).expect("file limit lock poisoned");
+            }
+            *open += 1;
+            FilePermit { limit }
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{FskvError, Store};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// How `Store::patch_json` merges fields that are objects on both sides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchDepth {
+    /// The patch's object replaces the stored one as a whole
+    Shallow,
+    /// The patch's object is merged into the stored one, and so on down
+    Deep,
+}
+
+impl Store {
+    /// Add `by` to the numeric `field` of the JSON object stored at `key`
+    /// (a missing field counts as 0) and return its new value. The whole
+    /// read-modify-write happens under the key's lock.
+    pub fn increment_field(&self, key: &str, field: &str, by: i64) -> Result<i64, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let mut object = self.get_object(key)?;
+
+        let current = match object.get(field) {
+            None => 0,
+            Some(n) => n.as_i64().ok_or_else(|| {
+                FskvError::InvalidValue(format!("field {:?} of {:?} isn't an integer", field, key))
+            })?,
+        };
+        let new = current.checked_add(by).ok_or_else(|| {
+            FskvError::InvalidValue(format!("field {:?} of {:?} would overflow", field, key))
+        })?;
+        object.insert(field.to_string(), Value::from(new));
+
+        self.write_locked(key, &Value::Object(object).to_string())
+            .map(|_| new)
+    }
+
+    /// Set every field of the JSON object `patch` in the JSON object
+    /// stored at `key`, leaving the fields it doesn't mention alone; with
+    /// `PatchDepth::Deep`, fields that are objects on both sides are
+    /// patched the same way rather than replaced. Fields set to `null` are
+    /// kept as such. The whole read-modify-write happens under the key's
+    /// lock.
+    pub fn patch_json(&self, key: &str, patch: &Value, depth: PatchDepth) -> Result<(), FskvError> {
+        let patch = match patch {
+            Value::Object(patch) => patch,
+            _ => {
+                return Err(FskvError::InvalidValue(format!(
+                    "patch for {:?} isn't a JSON object",
+                    key
+                )))
+            }
+        };
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let mut object = self.get_object(key)?;
+        merge(&mut object, patch, depth);
+        self.write_locked(key, &Value::Object(object).to_string())
+    }
+
+    /// The value of `key` deserialized from JSON, or `default` if there's no
+    /// such key. A value that's there but doesn't parse is still an error,
+    /// so corrupt data doesn't silently turn into the default.
+    pub fn get_typed_or<T: DeserializeOwned>(&self, key: &str, default: T) -> Result<T, FskvError> {
+        match self.get(key) {
+            Ok(value) => serde_json::from_str(&value).map_err(|e| {
+                FskvError::InvalidValue(format!("value of {:?} doesn't parse: {}", key, e))
+            }),
+            Err(e) if e.is_not_found() => Ok(default),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_object(&self, key: &str) -> Result<Map<String, Value>, FskvError> {
+        match parse_json(key, &self.get(key)?)? {
+            Value::Object(object) => Ok(object),
+            _ => Err(FskvError::InvalidValue(format!(
+                "value of {:?} isn't a JSON object",
+                key
+            ))),
+        }
+    }
+}
+
+fn merge(object: &mut Map<String, Value>, patch: &Map<String, Value>, depth: PatchDepth) {
+    for (field, value) in patch {
+        match (object.get_mut(field), value) {
+            (Some(Value::Object(inner)), Value::Object(patch)) if depth == PatchDepth::Deep => {
+                merge(inner, patch, depth)
+            }
+            _ => {
+                object.insert(field.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn parse_json(key: &str, value: &str) -> Result<Value, FskvError> {
+    serde_json::from_str(value)
+        .map_err(|e| FskvError::InvalidValue(format!("value of {:?} isn't JSON: {}", key, e)))
+}
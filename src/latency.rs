@@ -0,0 +1,100 @@
+use crate::Store;
+use hdrhistogram::Histogram;
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The operations whose latency `Store::latency_percentiles` reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    Get,
+    Put,
+    Update,
+    Delete,
+}
+
+/// How long an operation took, at a few percentiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    /// How many operations these are computed over.
+    pub count: u64,
+}
+
+// latencies recorded so far, in nanoseconds, shared by all clones of a store
+#[derive(Debug)]
+pub(crate) struct Latencies {
+    histograms: [Mutex<Histogram<u64>>; 4],
+}
+
+impl Latencies {
+    pub(crate) fn new() -> Latencies {
+        // 3 significant digits, resizing as slower operations turn up
+        let histogram = || Mutex::new(Histogram::new(3).expect("invalid histogram precision"));
+        Latencies {
+            histograms: [histogram(), histogram(), histogram(), histogram()],
+        }
+    }
+
+    fn histogram(&self, op: OpKind) -> &Mutex<Histogram<u64>> {
+        &self.histograms[op as usize]
+    }
+}
+
+// records how long it was around for when dropped
+pub(crate) struct Timer<'a> {
+    latencies: &'a Latencies,
+    op: OpKind,
+    start: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        let nanos = self
+            .start
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.latencies
+            .histogram(self.op)
+            .lock()
+            .expect("latency histogram lock poisoned")
+            .saturating_record(nanos);
+    }
+}
+
+impl Store {
+    /// The latency of `op` so far, for stores built with
+    /// `StoreBuilder::record_latencies`; all zeros otherwise, or before
+    /// any such operation. Failed operations count as well.
+    pub fn latency_percentiles(&self, op: OpKind) -> Percentiles {
+        let latencies = match &self.latencies {
+            Some(latencies) => latencies,
+            None => return Percentiles::default(),
+        };
+        let histogram = latencies
+            .histogram(op)
+            .lock()
+            .expect("latency histogram lock poisoned");
+        let at = |quantile| Duration::from_nanos(histogram.value_at_quantile(quantile));
+
+        Percentiles {
+            p50: at(0.50),
+            p95: at(0.95),
+            p99: at(0.99),
+            count: histogram.len(),
+        }
+    }
+
+    // time an operation until the returned guard is dropped, if recording
+    pub(crate) fn time(&self, op: OpKind) -> Option<Timer<'_>> {
+        self.latencies.as_ref().map(|latencies| Timer {
+            latencies,
+            op,
+            start: Instant::now(),
+        })
+    }
+}
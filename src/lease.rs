@@ -0,0 +1,82 @@
+use crate::{FskvError, Store};
+use std::time::{Duration, SystemTime};
+
+// leases are keys holding when they expire (in milliseconds since the epoch,
+// so every process reads them the same) and who holds them, as
+// `<expiry> <holder>`, written under the key's lock
+impl Store {
+    /// Take the lease on `key` for `holder` for `ttl`, if no one holds it or
+    /// the lease has expired, returning whether `holder` holds it now; e.g.
+    /// for a lock shared by several processes. A holder taking its own lease
+    /// again renews it.
+    pub fn lease(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool, FskvError> {
+        self.write_lease(key, holder, ttl, false)
+    }
+
+    /// Extend the lease `holder` has on `key` to `ttl` from now, returning
+    /// whether it did: not if it expired or someone else holds it.
+    pub fn renew(&self, key: &str, holder: &str, ttl: Duration) -> Result<bool, FskvError> {
+        self.write_lease(key, holder, ttl, true)
+    }
+
+    /// Give up the lease `holder` has on `key`, deleting it, returning
+    /// whether it did: not if someone else holds it (or it's gone).
+    pub fn release(&self, key: &str, holder: &str) -> Result<bool, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        match self.read_lease(key)? {
+            Some((current, _)) if current == holder => self.delete_locked(key).map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    // `lease` or, if `held`, `renew`
+    fn write_lease(
+        &self,
+        key: &str,
+        holder: &str,
+        ttl: Duration,
+        held: bool,
+    ) -> Result<bool, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let now = epoch_millis(SystemTime::now());
+        let free = match self.read_lease(key)? {
+            Some((current, expiry)) if expiry > now => current == holder,
+            _ => !held,
+        };
+        if !free {
+            return Ok(false);
+        }
+
+        let expiry = now.saturating_add(ttl.as_millis());
+        self.write_locked(key, &format!("{} {}", expiry, holder))
+            .map(|_| true)
+    }
+
+    // who holds the lease on a locked key and when it expires, if anyone
+    fn read_lease(&self, key: &str) -> Result<Option<(String, u128)>, FskvError> {
+        let value = match self.get(key) {
+            Ok(value) => value,
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match value.split_once(' ') {
+            Some((expiry, holder)) => match expiry.parse() {
+                Ok(expiry) => Ok(Some((holder.to_string(), expiry))),
+                Err(_) => Err(malformed(key)),
+            },
+            None => Err(malformed(key)),
+        }
+    }
+}
+
+fn malformed(key: &str) -> FskvError {
+    FskvError::InvalidValue(format!("value of {:?} isn't a lease", key))
+}
+
+fn epoch_millis(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("failed to get system time")
+        .as_millis()
+}
@@ -1,16 +1,170 @@
-use md5;
+use coalesce::Coalescer;
+use files::FileLimit;
+use flate2::read::GzDecoder;
+use latency::Latencies;
+use log::{debug, trace, warn};
+use ring::Ring;
+use std::fmt;
 use std::fs;
 use std::io::prelude::*;
-use std::io::Error;
+use std::io::{BufReader, Error, ErrorKind};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod atomic;
+mod backend;
+mod builder;
+mod checksum;
+mod cleanup;
+mod coalesce;
+mod codec;
+mod count;
+mod counter;
+mod encoding;
+mod error;
+mod events;
+mod export;
+mod files;
+#[cfg(feature = "serde")]
+mod json;
+mod latency;
+mod lease;
+mod lock;
+mod marker;
+mod namespace;
+mod ops;
+mod parallel;
+mod prefetch;
+mod promote;
+mod records;
+mod repair;
+mod ring;
+mod scan;
+mod times;
+mod token;
+mod usage;
+mod validate;
+mod variants;
+mod version;
+
+pub use atomic::KeepEnd;
+pub use backend::{Backend, FsBackend};
+pub use builder::StoreBuilder;
+pub use checksum::{ChecksumAlgorithm, DedupStats, Diff};
+pub use encoding::Encoding;
+pub use error::FskvError;
+pub use events::{MutationEvent, MutationKind};
+#[cfg(feature = "serde")]
+pub use json::PatchDepth;
+pub use latency::{OpKind, Percentiles};
+pub use ops::{Op, OpResult};
+pub use promote::promote;
+pub use repair::ReconcileReport;
+pub use token::{GetResult, VersionToken};
+pub use variants::ContentEncoding;
 
 const DIRECTORY_TREE_HEIGHT: usize = 3;
 const SINGLE_DIRECTORY_LENGTH: usize = 4;
+// the kinds of files the store keeps next to each key
+const VERSION_SIDECAR: &str = "version";
+const LOCK_SIDECAR: &str = "lock";
+const CREATED_SIDECAR: &str = "created";
+// the first two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// how long writes wait before trying again, doubling after every attempt
+// up to the max, so whoever is getting in the way has time to finish
+const RETRY_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// user-supplied rules a key must follow before it can be written
+type KeyValidator = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+// splits a key into the part naming its top-level directory and the
+// part that's hashed
+type KeyPartition = Arc<dyn Fn(&str) -> (&str, &str) + Send + Sync>;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone)]
 pub struct Store {
-    root_directory: &'static str,
+    root_directory: PathBuf,
+    key_validator: Option<KeyValidator>,
+    auto_decompress: bool,
+    trim_trailing_newlines: bool,
+    temp_dir: Option<PathBuf>,
+    key_partition: Option<KeyPartition>,
+    versioned: bool,
+    snapshots: bool,
+    max_keys: Option<usize>,
+    strict_not_found: bool,
+    empty_is_absent: bool,
+    // whether to record when keys are created
+    creation_times: bool,
+    // appended to keys to name their files, e.g. ".json"; possibly empty
+    value_extension: String,
+    // hashed along with every key, so its bucket can't be guessed from it
+    key_salt: String,
+    // the buckets keys are spread over, if not the MD5 tree
+    ring: Option<Arc<Ring>>,
+    // whether every value starts with a byte naming its codec
+    value_codecs: bool,
+    // permissions of the value files and buckets created, if not the default
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    // updates held back to be written together, if enabled
+    coalescer: Option<Arc<Coalescer>>,
+    // how long operations took, if recording
+    latencies: Option<Arc<Latencies>>,
+    // how many files parallel operations may have open at once, if limited
+    file_limit: Option<Arc<FileLimit>>,
+    // whether to check that updates leave a regular file behind
+    verify_renames: bool,
+    // how many times `get_checked` reads a value again on a mismatch
+    checksum_retries: usize,
+    // how many times `replace_value` tries again on a transient failure
+    update_retries: usize,
+    // how `put_reader_checked` checksums values
+    checksum_algorithm: ChecksumAlgorithm,
+    // what `append_record` ends each record with
+    record_delimiter: String,
+    // what the values are read and written with
+    backend: Arc<dyn Backend>,
+    // shared by all clones, so they all notify the same subscribers
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<MutationEvent>>>>,
+}
+
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Store")
+            .field("root_directory", &self.root_directory)
+            .field("key_validator", &self.key_validator.is_some())
+            .field("auto_decompress", &self.auto_decompress)
+            .field("trim_trailing_newlines", &self.trim_trailing_newlines)
+            .field("temp_dir", &self.temp_dir)
+            .field("key_partition", &self.key_partition.is_some())
+            .field("versioned", &self.versioned)
+            .field("snapshots", &self.snapshots)
+            .field("max_keys", &self.max_keys)
+            .field("strict_not_found", &self.strict_not_found)
+            .field("empty_is_absent", &self.empty_is_absent)
+            .field("creation_times", &self.creation_times)
+            .field("value_extension", &self.value_extension)
+            .field("key_salt", &!self.key_salt.is_empty())
+            .field("ring", &self.ring)
+            .field("value_codecs", &self.value_codecs)
+            .field("file_mode", &self.file_mode)
+            .field("dir_mode", &self.dir_mode)
+            .field("coalescer", &self.coalescer)
+            .field("latencies", &self.latencies.is_some())
+            .field("file_limit", &self.file_limit)
+            .field("verify_renames", &self.verify_renames)
+            .field("checksum_retries", &self.checksum_retries)
+            .field("update_retries", &self.update_retries)
+            .field("checksum_algorithm", &self.checksum_algorithm)
+            .field("record_delimiter", &self.record_delimiter)
+            .finish()
+    }
 }
 
 impl Store {
@@ -20,9 +174,35 @@ impl Store {
         //
         // the "hopefully uniformly random" part should be ensured by
         // taking chunks of the MD5 sum digest
-        let digest = format!("{:x}", md5::compute(key));
-        let mut root = PathBuf::from(&self.root_directory);
-        for i in 0..DIRECTORY_TREE_HEIGHT {
+        let mut root = self.root_directory.clone();
+        // unless the key is partitioned, in which case its shard part
+        // is the first level and only the rest is hashed
+        let (first_level, hashed) = match &self.key_partition {
+            Some(partition) => match partition(key) {
+                (shard, rest) if self.check_key(shard).is_ok() => {
+                    root.push(shard);
+                    (1, rest)
+                }
+                _ => (0, key),
+            },
+            None => (0, key),
+        };
+        let digest = match self.key_salt.is_empty() {
+            true => md5::compute(hashed),
+            false => md5::compute(format!("{}{}", self.key_salt, hashed)),
+        };
+        let digest = match &self.ring {
+            // the bucket names the first level hashed, the rest are all
+            // the same
+            Some(ring) => format!(
+                "{}{:04x}{}",
+                "0".repeat(SINGLE_DIRECTORY_LENGTH * first_level),
+                ring.bucket(&digest.0),
+                "0".repeat(SINGLE_DIRECTORY_LENGTH * (DIRECTORY_TREE_HEIGHT - first_level - 1))
+            ),
+            None => format!("{:x}", digest),
+        };
+        for i in first_level..DIRECTORY_TREE_HEIGHT {
             root.push(
                 digest
                     .chars()
@@ -32,79 +212,805 @@ impl Store {
             );
         }
 
-        return root;
+        root
     }
 
-    pub fn new(root_dir: &'static str, create: bool) -> Result<Store, Error> {
-        let store = Store {
-            root_directory: root_dir,
-        };
+    // the file holding the value of `key`
+    pub(crate) fn key_file(&self, key: &str) -> PathBuf {
+        self.get_key_path(key)
+            .join(format!("{}{}", key, self.value_extension))
+    }
+
+    // create the directories of a key's bucket, with the configured mode
+    pub(crate) fn create_bucket(&self, path: &Path) -> Result<(), Error> {
+        self.backend.create_dir_all(path, self.dir_mode)
+    }
+
+    // create a file in the bucket at `path` with `create`; should the
+    // bucket have been removed as empty (see `delete_where`) since it was
+    // created, it's created again and `create` is tried once more
+    pub(crate) fn in_bucket<T>(
+        &self,
+        path: &Path,
+        mut create: impl FnMut() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        match create() {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                self.create_bucket(path)?;
+                create()
+            }
+            r => r,
+        }
+    }
+
+    // how to create a new value file, with the configured mode
+    pub(crate) fn value_file_options(&self) -> fs::OpenOptions {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.file_mode {
+            options.mode(mode);
+        }
+        options
+    }
 
-        if create {
-            fs::create_dir_all(root_dir).and(Ok(store))
+    // the built-in rules every key must follow for it to map to a single
+    // file inside its bucket
+    fn check_key(&self, key: &str) -> Result<(), FskvError> {
+        if key.is_empty() {
+            Err(FskvError::InvalidKey("key is empty".to_string()))
+        } else if key.contains(['/', '\\', '\0']) {
+            Err(FskvError::InvalidKey(format!(
+                "key {:?} contains a path separator or NUL",
+                key
+            )))
+        } else if key.starts_with('.') {
+            // this also rules out `.` and `..`; dot files are reserved
+            // for the store's own bookkeeping
+            Err(FskvError::InvalidKey(format!(
+                "key {:?} starts with a dot",
+                key
+            )))
         } else {
-            fs::metadata(root_dir).and(Ok(store))
+            Ok(())
+        }
+    }
+
+    // validation for keys about to be written: the built-in rules plus
+    // those of the user, if any
+    //
+    // this also holds off snapshots until the returned guard is dropped, so
+    // hold on to it for as long as the write takes
+    fn check_key_for_write(&self, key: &str) -> Result<Option<fs::File>, FskvError> {
+        self.validate_key(key)?;
+        // an update held back mustn't land after whatever comes next
+        self.flush_key(key)?;
+        self.lock_store(false)
+    }
+
+    fn validate_key(&self, key: &str) -> Result<(), FskvError> {
+        self.check_key(key)?;
+        match &self.key_validator {
+            Some(validator) => validator(key).map_err(FskvError::InvalidKey),
+            None => Ok(()),
+        }
+    }
+
+    pub fn new(root_dir: &str, create: bool) -> Result<Store, FskvError> {
+        StoreBuilder::new(root_dir).create(create).build()
+    }
+
+    /// Like `new` (creating the store if needed), but when there's no store
+    /// at `root_dir` yet also `put_batch` the `seed` entries in it; e.g. for
+    /// first-run setup that's safe to repeat, since after that they're left
+    /// alone, even if they've changed or been deleted since. The store only
+    /// shows up once it has all of them, so if seeding fails the next call
+    /// starts over.
+    pub fn new_with_seed(root_dir: &str, seed: &[(&str, &str)]) -> Result<Store, FskvError> {
+        // an empty directory is where the store is to be created
+        let has_store = || match fs::read_dir(root_dir) {
+            Ok(mut entries) => Ok(entries.next().is_some()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(FskvError::from(e)),
+        };
+
+        if !has_store()? {
+            // seed a store of its own next to it, then move it into place
+            // as a whole, which replaces an empty directory
+            let root = Path::new(root_dir);
+            let mut name = root.file_name().unwrap_or_default().to_os_string();
+            name.push(temp_file_name());
+            let staging = root.with_file_name(name);
+            let moved = StoreBuilder::new(&staging)
+                .create(true)
+                .build()
+                .and_then(|store| store.put_batch(seed))
+                .and_then(|_| fs::rename(&staging, root).map_err(FskvError::from));
+            if let Err(e) = moved {
+                fs::remove_dir_all(&staging).unwrap_or(());
+                // unless someone else's got there first
+                if !has_store()? {
+                    return Err(e);
+                }
+            }
+        }
+        StoreBuilder::new(root_dir).create(true).build()
+    }
+
+    /// The absolute, canonical, path of the store's root directory, which
+    /// is what it uses however it was given when opening it.
+    pub fn root_path(&self) -> &Path {
+        &self.root_directory
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        let _timer = self.time(OpKind::Put);
+        self.put_with_codec(key, value, None)
+    }
+
+    /// Like `put`, but also succeeding, without writing anything, if the
+    /// key already holds exactly `value`; e.g. for setup scripts that may
+    /// run more than once. It still fails with `FskvError::KeyExists` if
+    /// the key holds anything else.
+    pub fn put_idempotent(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        match self.put(key, value) {
+            Err(FskvError::KeyExists(_)) if self.get_bytes(key)? == value.as_bytes() => Ok(()),
+            r => r,
         }
     }
 
-    pub fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+    // `put` of a value as it's stored
+    pub(crate) fn put_encoded(&self, key: &str, stored: &[u8]) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
         // create the directory structure
-        let key_path = self.get_key_path(&key);
-        fs::create_dir_all(&key_path)?;
+        let key_path = self.get_key_path(key);
+        self.create_bucket(&key_path)?;
+        let _lock = match self.versioned {
+            true => Some(self.lock_key(key, true)?),
+            false => None,
+        };
+        self.put_stored(key, stored)
+    }
+
+    // put a key whose bucket exists and whose lock is held if need be
+    pub(crate) fn put_locked(&self, key: &str, value: &[u8]) -> Result<(), FskvError> {
+        self.put_stored(key, &self.encode_value(value, None)?)
+    }
+
+    // `put_locked` of a value as it's stored
+    pub(crate) fn put_stored(&self, key: &str, value: &[u8]) -> Result<(), FskvError> {
         // now save the thing using create_new -- it's atomic
-        let key_file = Path::new(&key_path).join(&key);
-        fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&key_file)
-            .and_then(|mut f| f.write_all(&value.as_bytes()))
+        let key_file = self.key_file(key);
+        trace!("put {:?}: creating {}", key, key_file.display());
+        let created = self.in_bucket(&self.get_key_path(key), || {
+            self.backend.open(&key_file, &self.value_file_options())
+        });
+        let mut f = match created {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                debug!("put {:?}: key already exists", key);
+                return Err(FskvError::KeyExists(key.to_string()));
+            }
+            Err(e) => {
+                warn!(
+                    "put {:?}: failed to create {}: {}",
+                    key,
+                    key_file.display(),
+                    e
+                );
+                return Err(FskvError::from(e));
+            }
+        };
+        // only new keys count against the limit, so there's no room to
+        // make until it's clear this is one
+        if let Err(e) = self.reserve_key() {
+            drop(f);
+            self.backend.remove_file(&key_file).unwrap_or(());
+            return Err(e);
+        }
+        if let Err(e) = f.write_all(value) {
+            warn!(
+                "put {:?}: failed to write {}: {}",
+                key,
+                key_file.display(),
+                e
+            );
+            self.release_key()?;
+            return Err(FskvError::from(e));
+        }
+        trace!("put {:?}: wrote {} bytes", key, value.len());
+        self.finish_put(key)
+    }
+
+    // move a value written aside into place as the new key `key`, whose
+    // bucket exists and whose lock is held if need be; the file written
+    // aside is removed either way
+    pub(crate) fn link_new_value(&self, key: &str, tmp_file: &Path) -> Result<(), FskvError> {
+        // linking never replaces an existing file
+        let key_file = self.key_file(key);
+        let linked = fs::hard_link(tmp_file, &key_file)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => FskvError::KeyExists(key.to_string()),
+                _ => FskvError::from(e),
+            })
+            .and_then(|_| {
+                // as with `put`, only once it's clear the key is new
+                self.reserve_key().inspect_err(|_| {
+                    fs::remove_file(&key_file).unwrap_or(());
+                })
+            });
+        fs::remove_file(tmp_file)?;
+        linked
+    }
+
+    // the bookkeeping after a new key is in place
+    pub(crate) fn finish_put(&self, key: &str) -> Result<(), FskvError> {
+        if self.versioned {
+            self.write_version(key, 1)?;
+        }
+        if self.creation_times {
+            self.write_created(key)?;
+        }
+        self.notify(key, MutationKind::Put);
+        Ok(())
+    }
+
+    /// Put every entry, or none of them: on the first failure the entries
+    /// already written by this call are deleted and the error is returned.
+    pub fn put_batch(&self, entries: &[(&str, &str)]) -> Result<(), FskvError> {
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if let Err(e) = self.put(key, value) {
+                // roll back; there's not much we can do if this fails too
+                for (key, _) in &entries[..i] {
+                    self.delete(key).unwrap_or(());
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Put each entry independently, retrying transient failures to create
+    /// its directory up to `retries` times, and report one result per entry
+    /// (in the same order) instead of aborting the whole batch.
+    pub fn put_batch_with_retries(
+        &self,
+        entries: &[(&str, &str)],
+        retries: usize,
+    ) -> Vec<Result<(), FskvError>> {
+        entries
+            .iter()
+            .map(|(key, value)| {
+                self.validate_key(key)?;
+                // only the directory creation is retried: if the file
+                // itself can't be created (e.g. the key already exists)
+                // trying again won't help
+                let key_path = self.get_key_path(key);
+                let mut attempt = 0;
+                loop {
+                    match self.create_bucket(&key_path) {
+                        Err(ref e) if attempt < retries && is_transient(e) => attempt += 1,
+                        r => break r?,
+                    }
+                }
+                self.put(key, value)
+            })
+            .collect()
+    }
+
+    pub fn get(&self, key: &str) -> Result<String, FskvError> {
+        let _timer = self.time(OpKind::Get);
+        self.check_key(key)?;
+        let mut bytes = Vec::new();
+        self.value_reader(key, self.open_value(key)?)?
+            .read_to_end(&mut bytes)?;
+        self.decode_value(bytes)
+    }
+
+    /// Like `get`, but giving up with `FskvError::Timeout` if the value
+    /// can't be read by `deadline`, which is checked between chunks; e.g.
+    /// for a request handler sharing one deadline across several reads.
+    pub fn get_deadline(&self, key: &str, deadline: Instant) -> Result<String, FskvError> {
+        self.check_key(key)?;
+        let mut file = self.value_reader(key, self.open_value(key)?)?;
+        let mut bytes = Vec::new();
+        let mut buf = [0; 8192];
+
+        loop {
+            if Instant::now() >= deadline {
+                return Err(FskvError::Timeout(key.to_string()));
+            }
+            match file.read(&mut buf)? {
+                0 => break,
+                n => bytes.extend_from_slice(&buf[..n]),
+            }
+        }
+        self.decode_value(bytes)
     }
 
-    pub fn get(&self, key: &str) -> Result<String, Error> {
-        let key_path = self.get_key_path(&key).join(&key);
+    // what `get` makes of the bytes stored
+    pub(crate) fn decode_value(&self, bytes: Vec<u8>) -> Result<String, FskvError> {
         let mut value = String::new();
+        if self.auto_decompress && bytes.starts_with(&GZIP_MAGIC) {
+            GzDecoder::new(&bytes[..]).read_to_string(&mut value)?;
+        } else {
+            value = String::from_utf8(bytes)
+                .map_err(|e| FskvError::from(Error::new(ErrorKind::InvalidData, e)))?;
+        }
+
+        if self.trim_trailing_newlines {
+            let trimmed = value.trim_end_matches(['\n', '\r']).len();
+            value.truncate(trimmed);
+        }
+        Ok(value)
+    }
+
+    /// The value of `key` exactly as it was written, whatever the store does
+    /// to values on `get`.
+    pub fn get_bytes(&self, key: &str) -> Result<Vec<u8>, FskvError> {
+        self.check_key(key)?;
+        let mut value = Vec::new();
+        self.value_reader(key, self.open_value(key)?)?
+            .read_to_end(&mut value)?;
+        Ok(value)
+    }
 
-        fs::File::open(&key_path)
-            .and_then(|mut f| f.read_to_string(&mut value))
-            .map(|_| value)
-    }
-
-    pub fn update(&self, key: &str, value: &str) -> Result<(), Error> {
-        let key_path = self.get_key_path(&key);
-        // do upsert
-        match fs::metadata(&key_path) {
-            // write to a new, random, file and then move
-            Ok(_) => {
-                let now = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("failed to get system time");
-                // even under load, the probability of 2 requests
-                // happening at the same nanosecond is low; very low
-                let tmp = format!("{}", now.as_nanos());
-                let tmp_file = Path::new(&key_path).join(&tmp);
-                let key_file = Path::new(&key_path).join(&key);
-                // write to the temporary file and then move to the
-                // actual key; or exit on error
-                fs::OpenOptions::new()
-                    .write(true)
-                    .create_new(true)
-                    .open(&tmp_file)
-                    .and_then(|mut f| f.write_all(&value.as_bytes()))
-                    .and_then(|_| fs::rename(&tmp_file, &key_file))
-            }
-            // just create a new entry
-            Err(_) => self.put(&key, &value),
-        }
-    }
-
-    pub fn delete(&self, key: &str) -> Result<(), Error> {
-        fs::remove_file(&self.get_key_path(&key).join(&key))
+    /// Read the value of `key` into `buf`, replacing its contents, and return
+    /// its length. Reusing the same buffer across reads saves allocating one
+    /// per value in tight loops.
+    pub fn read_into(&self, key: &str, buf: &mut Vec<u8>) -> Result<usize, FskvError> {
+        self.check_key(key)?;
+        buf.clear();
+        Ok(self
+            .value_reader(key, self.open_value(key)?)?
+            .read_to_end(buf)?)
+    }
+
+    /// Whether the value of `key` is valid UTF-8, i.e. whether `get` can
+    /// read it, e.g. to decide how to display it. It's read a chunk at a
+    /// time, stopping at the first invalid byte.
+    pub fn is_text(&self, key: &str) -> Result<bool, FskvError> {
+        self.check_key(key)?;
+        let mut file = self.value_reader(key, self.open_value(key)?)?;
+        let mut buf = [0; 8192];
+        // bytes of a character split across chunks, carried over
+        let mut pending = 0;
+
+        loop {
+            let n = file.read(&mut buf[pending..])?;
+            if n == 0 {
+                return Ok(pending == 0);
+            }
+            let len = pending + n;
+            match std::str::from_utf8(&buf[..len]) {
+                Ok(_) => pending = 0,
+                // an incomplete character at the end of the chunk
+                Err(e) if e.error_len().is_none() => {
+                    let valid = e.valid_up_to();
+                    buf.copy_within(valid..len, 0);
+                    pending = len - valid;
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+
+    /// Iterate over the lines of a value without loading all of it, e.g. for
+    /// values holding logs.
+    pub fn get_lines(
+        &self,
+        key: &str,
+    ) -> Result<impl Iterator<Item = Result<String, FskvError>>, FskvError> {
+        self.check_key(key)?;
+        let file = self.value_reader(key, self.open_value(key)?)?;
+        Ok(BufReader::new(file)
+            .lines()
+            .map(|line| line.map_err(FskvError::from)))
+    }
+
+    /// The read-only file holding the value of `key`, for APIs that need a
+    /// `File` (to seek, look at its metadata or pass its descriptor along).
+    /// Since `update` replaces the file rather than writing to it, a handle
+    /// opened before an update keeps reading the old value, while appends
+    /// do show up through it. In stores with `StoreBuilder::value_codecs`,
+    /// the file holds the value's header and, if it is, the value compressed.
+    pub fn open_value_file(&self, key: &str) -> Result<fs::File, FskvError> {
+        self.check_key(key)?;
+        self.open_value(key)
+    }
+
+    /// Whether there's a value for `key`.
+    pub fn contains_key(&self, key: &str) -> Result<bool, FskvError> {
+        self.check_key(key)?;
+        match self.open_value(key) {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // open the file holding the value of an already validated key
+    pub(crate) fn open_value(&self, key: &str) -> Result<fs::File, FskvError> {
+        let key_file = self.key_file(key);
+        let file = self
+            .backend
+            .open(&key_file, fs::OpenOptions::new().read(true));
+        trace!("get {:?}: opening {}", key, key_file.display());
+        let file = file.map_err(|e| match e.kind() {
+            ErrorKind::NotFound if self.strict_not_found && !self.get_key_path(key).is_dir() => {
+                FskvError::BucketNotFound(key.to_string())
+            }
+            ErrorKind::NotFound => FskvError::KeyNotFound(key.to_string()),
+            _ => {
+                warn!("get {:?}: failed to open its value: {}", key, e);
+                FskvError::from(e)
+            }
+        })?;
+        // in stores with codecs, empty values are only ever the header
+        let empty_len = match self.value_codecs {
+            true => 1,
+            false => 0,
+        };
+        if self.empty_is_absent && file.metadata()?.len() == empty_len {
+            return Err(FskvError::KeyNotFound(key.to_string()));
+        }
+        Ok(file)
+    }
+
+    pub fn update(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        let _timer = self.time(OpKind::Update);
+        self.validate_key(key)?;
+        match &self.coalescer {
+            Some(coalescer) => self.coalesce(coalescer, key, value),
+            None => self.write_update(key, value),
+        }
+    }
+
+    // `update` right away
+    pub(crate) fn write_update(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        let _writing = self.lock_store(false)?;
+        if self.versioned {
+            return self.update_versioned(key, value, None).map(|_| ());
+        }
+        self.replace_value(key, value)
+    }
+
+    // upsert the value of an already validated key
+    pub(crate) fn replace_value(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        self.replace_stored(key, &self.encode_value(value.as_bytes(), None)?)
+    }
+
+    // `replace_value` with a value as it's stored
+    pub(crate) fn replace_stored(&self, key: &str, value: &[u8]) -> Result<(), FskvError> {
+        let key_path = self.get_key_path(key);
+        // the temporary file is written next to the key, so make sure
+        // the bucket is there
+        if self.backend.metadata(&key_path).is_err() {
+            self.create_bucket(&key_path)?;
+        }
+        let key_file = self.key_file(key);
+        let new_key = (self.max_keys.is_some() || self.creation_times) && !key_file.exists();
+        if new_key {
+            self.reserve_key()?;
+        }
+        let options = self.value_file_options();
+        let backend = self.backend.as_ref();
+        let mut retries = 0;
+        let mut backoff = RETRY_BACKOFF;
+        let written = loop {
+            let written = self.in_bucket(&key_path, || match &self.temp_dir {
+                Some(temp_dir) => {
+                    write_atomically_via(backend, &options, temp_dir, &key_file, value)
+                }
+                None => write_atomically_using(backend, &options, &key_file, value),
+            });
+            match written {
+                Err(ref e) if retries < self.update_retries && may_be_transient(e) => {
+                    retries += 1;
+                    debug!("update {:?}: retrying ({}) after: {}", key, retries, e);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                }
+                written => break written,
+            }
+        };
+        if let Err(e) = written {
+            warn!(
+                "update {:?}: failed to replace {}: {}",
+                key,
+                key_file.display(),
+                e
+            );
+            if new_key {
+                self.release_key()?;
+            }
+            return Err(match not_a_file(&key_file) {
+                Some(e) => e,
+                None => FskvError::from(e),
+            });
+        }
+        if self.verify_renames {
+            if let Some(e) = not_a_file(&key_file) {
+                warn!("update {:?}: {}", key, e);
+                return Err(e);
+            }
+        }
+        if new_key && self.creation_times {
+            self.write_created(key)?;
+        }
+        trace!("update {:?}: renamed into {}", key, key_file.display());
+        self.notify(key, MutationKind::Update);
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), FskvError> {
+        let _timer = self.time(OpKind::Delete);
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = match self.versioned {
+            true => Some(self.lock_key(key, true)?),
+            false => None,
+        };
+        self.delete_locked(key)
+    }
+
+    // delete a key whose lock is held if need be
+    pub(crate) fn delete_locked(&self, key: &str) -> Result<(), FskvError> {
+        let key_file = self.key_file(key);
+        match self.backend.remove_file(&key_file) {
+            Ok(()) => trace!("delete {:?}: removed {}", key, key_file.display()),
+            Err(e) => {
+                // a missing key isn't worth more than a debug message
+                match e.kind() {
+                    ErrorKind::NotFound => debug!("delete {:?}: no such key", key),
+                    _ => warn!(
+                        "delete {:?}: failed to remove {}: {}",
+                        key,
+                        key_file.display(),
+                        e
+                    ),
+                }
+                return Err(not_found_as(e, key));
+            }
+        }
+        self.release_key()?;
+        if self.versioned {
+            self.remove_sidecar(key, VERSION_SIDECAR)?;
+        }
+        // whether or not the store records them now, it may have when the
+        // key was put
+        self.remove_sidecar(key, CREATED_SIDECAR)?;
+        self.remove_variants(key)?;
+        self.remove_checksums(key)?;
+        self.notify(key, MutationKind::Delete);
+        Ok(())
+    }
+
+    // the path of one of the store's own files about `key`, which live
+    // next to it as dot files
+    pub(crate) fn sidecar_path(&self, key: &str, kind: &str) -> PathBuf {
+        self.get_key_path(key).join(format!(".{}.{}", key, kind))
+    }
+
+    pub(crate) fn remove_sidecar(&self, key: &str, kind: &str) -> Result<(), FskvError> {
+        match fs::remove_file(self.sidecar_path(key, kind)) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            r => r.map_err(FskvError::from),
+        }
+    }
+
+    pub(crate) fn key_files(&self) -> KeyFiles {
+        KeyFiles::new(&self.root_directory, &self.value_extension)
+    }
+}
+
+// iterate over every key in the store as (key, path to its file), by
+// walking the directory tree; keys live only at the bottom level
+pub(crate) struct KeyFiles {
+    // one directory listing per level, along with its depth
+    stack: Vec<(fs::ReadDir, usize)>,
+    // error opening the root directory, reported on the first call
+    error: Option<Error>,
+    // what key files end with, to be stripped off
+    extension: String,
+}
+
+impl KeyFiles {
+    pub(crate) fn new(root: &Path, extension: &str) -> KeyFiles {
+        KeyFiles::at_depth(root, 0, extension)
+    }
+
+    // the keys under `dir`, which is `depth` levels below the root
+    pub(crate) fn at_depth(dir: &Path, depth: usize, extension: &str) -> KeyFiles {
+        let (stack, error) = match fs::read_dir(dir) {
+            Ok(dir) => (vec![(dir, depth)], None),
+            Err(e) => (vec![], Some(e)),
+        };
+        KeyFiles {
+            stack,
+            error,
+            extension: extension.to_string(),
+        }
     }
 }
 
+impl Iterator for KeyFiles {
+    type Item = Result<(String, PathBuf), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        while let Some((dir, depth)) = self.stack.last_mut() {
+            let depth = *depth;
+            let entry = match dir.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let file_type = match entry.file_type() {
+                Ok(t) => t,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if depth < DIRECTORY_TREE_HEIGHT {
+                if file_type.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+                    match fs::read_dir(entry.path()) {
+                        Ok(d) => self.stack.push((d, depth + 1)),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            } else if file_type.is_file() {
+                // keys are always valid UTF-8 and never start with a dot,
+                // anything else isn't a key
+                let name = match entry.file_name().into_string() {
+                    Ok(name) if !name.starts_with('.') => name,
+                    _ => continue,
+                };
+                match name.strip_suffix(self.extension.as_str()) {
+                    Some(key) if !key.is_empty() => {
+                        return Some(Ok((key.to_string(), entry.path())))
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// replace the contents of `path` as a whole: write to a new, random, file
+// next to it and then move that into place
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    write_atomically_using(
+        &FsBackend,
+        fs::OpenOptions::new().write(true).create_new(true),
+        path,
+        contents,
+    )
+}
+
+// `write_atomically` through `backend`, creating the temporary file with
+// `options`
+fn write_atomically_using(
+    backend: &dyn Backend,
+    options: &fs::OpenOptions,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), Error> {
+    let tmp_file = path.with_file_name(temp_file_name());
+    // write to the temporary file and then move to the
+    // actual key; or exit on error
+    backend
+        .open(&tmp_file, options)
+        .and_then(|mut f| f.write_all(contents))
+        .and_then(|_| backend.rename(&tmp_file, path))
+        .inspect_err(|_| {
+            backend.remove_file(&tmp_file).unwrap_or(());
+        })
+}
+
+// like `write_atomically`, but with the temporary file in `temp_dir`; if
+// that's on another file system it can't simply be renamed, so it's
+// copied next to `path` and moved into place from there
+fn write_atomically_via(
+    backend: &dyn Backend,
+    options: &fs::OpenOptions,
+    temp_dir: &Path,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), Error> {
+    let tmp_file = temp_dir.join(temp_file_name());
+    backend
+        .open(&tmp_file, options)
+        .and_then(|mut f| f.write_all(contents))
+        .inspect_err(|_| {
+            backend.remove_file(&tmp_file).unwrap_or(());
+        })?;
+
+    let result = match backend.rename(&tmp_file, path) {
+        Err(ref e) if e.kind() == ErrorKind::CrossesDevices => {
+            let local_file = path.with_file_name(temp_file_name());
+            fs::copy(&tmp_file, &local_file)
+                .and_then(|_| backend.rename(&local_file, path))
+                .inspect_err(|_| {
+                    backend.remove_file(&local_file).unwrap_or(());
+                })
+        }
+        r => r,
+    };
+    // either way the file in `temp_dir` isn't needed anymore, if it's
+    // still there
+    if tmp_file.exists() {
+        backend.remove_file(&tmp_file).unwrap_or(());
+    }
+    result
+}
+
+// whether a failed write may well succeed if tried again, rather than
+// fail the same way
+fn may_be_transient(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::AlreadyExists
+        | ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::ResourceBusy => true,
+        // what replacing a file someone else has open fails with on Windows;
+        // elsewhere it's down to permissions, which won't change by retrying
+        ErrorKind::PermissionDenied => cfg!(windows),
+        _ => false,
+    }
+}
+
+// `e` as `FskvError::KeyNotFound` for `key` if that's what it means
+fn not_found_as(e: Error, key: &str) -> FskvError {
+    match e.kind() {
+        ErrorKind::NotFound => FskvError::KeyNotFound(key.to_string()),
+        _ => FskvError::from(e),
+    }
+}
+
+// the error for a key's path holding something other than its value file,
+// e.g. a directory someone else created there, if it does
+fn not_a_file(path: &Path) -> Option<FskvError> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_file() => Some(FskvError::CorruptStore(format!(
+            "{} isn't a regular file",
+            path.display()
+        ))),
+        _ => None,
+    }
+}
+
+pub(crate) fn temp_file_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("failed to get system time");
+    // even under load, the probability of 2 requests
+    // happening at the same nanosecond is low; very low
+    format!(".{}.tmp", now.as_nanos())
+}
+
+// errors creating a directory that may go away on their own, e.g. a
+// concurrent cleanup removing an empty parent while we're creating it
+fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted | ErrorKind::NotFound | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    )
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
     use std::vec::Vec;
 
     fn teardown(directories: Vec<&str>) {
@@ -115,8 +1021,8 @@ mod tests {
 
     #[test]
     fn test_new() {
-        // fail to create (assuming we're not running tests as root)
-        let ds = Store::new("/foo", true);
+        // fail to create (not even root can create a directory under a file)
+        let ds = Store::new("/dev/null/foo", true);
         assert_eq!(ds.is_ok(), false);
         // succeed creating a directory in `cwd`
         let ds = Store::new("fskv_test", true);
@@ -142,4 +1048,61 @@ mod tests {
 
         teardown(vec!["fskv_test"]);
     }
+
+    #[test]
+    fn test_auto_decompress() {
+        let ds = StoreBuilder::new("fskv_test_auto_decompress")
+            .create(true)
+            .auto_decompress(true)
+            .build()
+            .unwrap();
+        // a legacy value, gzipped by someone else
+        let key_path = ds.get_key_path("gzipped");
+        fs::create_dir_all(&key_path).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(b"plain content").unwrap();
+        fs::write(ds.key_file("gzipped"), gz.finish().unwrap()).unwrap();
+        assert_eq!(ds.put("plain", "plain content").is_ok(), true);
+
+        assert_eq!(ds.get("gzipped").unwrap(), "plain content");
+        assert_eq!(ds.get("plain").unwrap(), "plain content");
+        // it's opt-in: by default the compressed bytes aren't even UTF-8
+        let ds = Store::new("fskv_test_auto_decompress", false).unwrap();
+        assert_eq!(ds.get("gzipped").is_ok(), false);
+        assert_eq!(ds.get("plain").unwrap(), "plain content");
+
+        teardown(vec!["fskv_test_auto_decompress"]);
+    }
+
+    #[test]
+    fn test_depth_for() {
+        // each level has 16^4 directories
+        assert_eq!(count::depth_for(0, 1), 1);
+        assert_eq!(count::depth_for(65536 * 10, 10), 1);
+        assert_eq!(count::depth_for(65536 * 10 + 1, 10), 2);
+        assert_eq!(count::depth_for(65536 * 65536, 1), 2);
+        assert_eq!(count::depth_for(65536 * 65536 + 1, 1), 3);
+        assert_eq!(count::depth_for(usize::MAX, 1), 4);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let ds = Store::new("fskv_test_unsupported_version", true);
+        assert_eq!(ds.is_ok(), true);
+        let root = Path::new("fskv_test_unsupported_version");
+        let mut marker = marker::Marker::read(root).unwrap().unwrap();
+        assert_eq!(marker.get("format_version"), Some("1"));
+        // pretend a newer version of the crate wrote it
+        let newer = marker::FORMAT_VERSION + 1;
+        marker.set("format_version", &newer.to_string());
+        marker.write(root).unwrap();
+        match Store::new("fskv_test_unsupported_version", false) {
+            Err(FskvError::UnsupportedVersion { found, supported }) => {
+                assert_eq!((found, supported), (newer, marker::FORMAT_VERSION))
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        teardown(vec!["fskv_test_unsupported_version"]);
+    }
 }
@@ -0,0 +1,126 @@
+use crate::{FskvError, Store, LOCK_SIDECAR};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+// the file at the root of stores with snapshots, which writers lock shared
+// and snapshots exclusively
+pub(crate) const STORE_LOCK_FILE: &str = ".fskv.lock";
+
+impl Store {
+    /// Whether something holds the whole store locked right now, as
+    /// `snapshot_keys` does, in this process or another one; e.g. for a
+    /// tool to decide whether to wait or come back later before doing
+    /// maintenance. This never waits itself, and the answer may be out of
+    /// date by the time it's returned.
+    pub fn is_locked(&self) -> Result<bool, FskvError> {
+        let file = match fs::File::open(self.root_directory.join(STORE_LOCK_FILE)) {
+            Ok(file) => file,
+            // it's only there once it's been locked
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(FskvError::from(e)),
+        };
+        // writers only ever share it
+        match file.try_lock_shared() {
+            Ok(()) => Ok(false),
+            Err(fs::TryLockError::WouldBlock) => Ok(true),
+            Err(fs::TryLockError::Error(e)) => Err(FskvError::from(e)),
+        }
+    }
+
+    // hold a lock on the whole store until the returned file is dropped, if
+    // it takes snapshots; it's never needed otherwise
+    pub(crate) fn lock_store(&self, exclusive: bool) -> Result<Option<fs::File>, FskvError> {
+        if !self.snapshots {
+            return Ok(None);
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root_directory.join(STORE_LOCK_FILE))?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(Some(file))
+    }
+
+    // hold an advisory lock on `key` until the returned file is dropped;
+    // it's a plain file lock, so it keeps out other processes too
+    pub(crate) fn lock_key(&self, key: &str, exclusive: bool) -> Result<fs::File, FskvError> {
+        let key_path = self.get_key_path(key);
+        self.create_bucket(&key_path)?;
+        let lock_file = self.sidecar_path(key, LOCK_SIDECAR);
+        loop {
+            // once it's there, the bucket isn't empty and stays
+            let file = self.in_bucket(&key_path, || {
+                fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&lock_file)
+            })?;
+            if exclusive {
+                file.lock()?;
+            } else {
+                file.lock_shared()?;
+            }
+            // it may have been removed by `remove_lock` while we waited for
+            // it, in which case whoever locks the new one doesn't know
+            // about us
+            match fs::metadata(&lock_file) {
+                Ok(metadata) if same_file(&metadata, &file.metadata()?) => return Ok(file),
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(FskvError::from(e)),
+            }
+        }
+    }
+
+    // remove the lock file of a key that's gone, held exclusively as `lock`,
+    // rather than leave it behind for good; only where `lock_key` can tell
+    // it's been removed
+    pub(crate) fn remove_lock(&self, key: &str, lock: fs::File) -> Result<(), FskvError> {
+        let removed = match cfg!(unix) {
+            true => self.remove_sidecar(key, LOCK_SIDECAR),
+            false => Ok(()),
+        };
+        drop(lock);
+        removed
+    }
+
+    // lock several keys at once, each either exclusively or shared; they're
+    // always taken in the same (sorted) order so two callers locking the
+    // same keys can't deadlock, and only once per key, exclusively if any
+    // of the requests for it is
+    pub(crate) fn lock_keys(&self, keys: &[(&str, bool)]) -> Result<Vec<fs::File>, FskvError> {
+        let mut wanted: BTreeMap<&str, bool> = BTreeMap::new();
+        for (key, exclusive) in keys {
+            *wanted.entry(key).or_insert(false) |= exclusive;
+        }
+
+        wanted
+            .into_iter()
+            .map(|(key, exclusive)| self.lock_key(key, exclusive))
+            .collect()
+    }
+}
+
+// whether two files are the same one, e.g. a lock file and what its path
+// leads to now
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+// lock files are only removed where files can be told apart like that
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
@@ -0,0 +1,99 @@
+use crate::FskvError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+// the file at the root of every store recording the settings it was
+// created with, so it can't be reopened in a way that misplaces keys
+pub(crate) const MARKER_FILE: &str = ".fskv";
+// bumped whenever the on-disk format changes in a way older versions of
+// the crate would misread; stores are created with the version their
+// settings need, so those that don't need a newer one can still be opened
+// by older versions
+pub(crate) const FORMAT_VERSION: u32 = 2;
+// the version of stores whose values have a codec header
+pub(crate) const VALUE_CODECS_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Marker {
+    settings: BTreeMap<String, String>,
+}
+
+impl Marker {
+    // `None` for stores created before markers existed
+    pub(crate) fn read(root: &Path) -> Result<Option<Marker>, FskvError> {
+        let contents = match fs::read_to_string(root.join(MARKER_FILE)) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FskvError::from(e)),
+        };
+
+        // one `name=value` setting per line
+        let mut settings = BTreeMap::new();
+        for line in contents.lines().filter(|l| !l.is_empty()) {
+            match line.split_once('=') {
+                Some((name, value)) => settings.insert(name.to_string(), value.to_string()),
+                None => {
+                    return Err(FskvError::from(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("malformed line in store marker: {:?}", line),
+                    )))
+                }
+            };
+        }
+
+        Ok(Some(Marker { settings }))
+    }
+
+    pub(crate) fn write(&self, root: &Path) -> Result<(), FskvError> {
+        let contents: String = self
+            .settings
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect();
+        // same as `update`: write aside and move into place
+        let tmp_file = root.join(format!("{}.tmp", MARKER_FILE));
+        fs::write(&tmp_file, contents)
+            .and_then(|_| fs::rename(&tmp_file, root.join(MARKER_FILE)))
+            .map_err(FskvError::from)
+    }
+
+    // what a store created by this version of the crate starts with, in
+    // the first format, before any setting that needs a newer one
+    pub(crate) fn new() -> Marker {
+        let mut marker = Marker::default();
+        marker.set("format_version", "1");
+        marker
+    }
+
+    // refuse stores written in a format newer than we understand
+    pub(crate) fn check_format_version(&self) -> Result<(), FskvError> {
+        // stores from before the version was recorded are all version 1
+        let found = match self.get("format_version") {
+            Some(version) => version.parse().map_err(|_| {
+                FskvError::from(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("malformed format version in store marker: {:?}", version),
+                ))
+            })?,
+            None => 1,
+        };
+
+        match found {
+            found if found > FORMAT_VERSION => Err(FskvError::UnsupportedVersion {
+                found,
+                supported: FORMAT_VERSION,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.settings.get(name).map(|v| v.as_str())
+    }
+
+    pub(crate) fn set(&mut self, name: &str, value: &str) {
+        self.settings.insert(name.to_string(), value.to_string());
+    }
+}
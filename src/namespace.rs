@@ -0,0 +1,27 @@
+use crate::marker::MARKER_FILE;
+use crate::{FskvError, Store};
+use std::fs;
+
+impl Store {
+    /// The names of the stores nested right under this one's root, e.g. one
+    /// per tenant, in order. Only directories holding a store marker count,
+    /// so the store's own buckets are never listed, and nothing is opened.
+    pub fn namespaces(&self) -> Result<Vec<String>, FskvError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.root_directory)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            match entry.file_name().into_string() {
+                Ok(name) if !name.starts_with('.') && entry.path().join(MARKER_FILE).is_file() => {
+                    names.push(name)
+                }
+                _ => (),
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{FskvError, Store};
+
+/// One of the operations `Store::apply` runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op<'a> {
+    /// `Store::put` a key and value
+    Put(&'a str, &'a str),
+    /// `Store::update` a key with a value
+    Update(&'a str, &'a str),
+    /// `Store::delete` a key
+    Delete(&'a str),
+    /// `Store::get` a key
+    Get(&'a str),
+}
+
+/// How one of the operations run by `Store::apply` went.
+#[derive(Debug)]
+pub enum OpResult {
+    /// A write went through
+    Done,
+    /// The value a `Get` read
+    Value(String),
+    /// The operation failed, with this error
+    Failed(FskvError),
+}
+
+impl Op<'_> {
+    fn key(&self) -> &str {
+        match self {
+            Op::Put(key, _) | Op::Update(key, _) | Op::Delete(key) | Op::Get(key) => key,
+        }
+    }
+}
+
+impl Store {
+    /// Run `ops` one after the other and return how each one went, in the
+    /// same order; e.g. for an RPC layer taking scripts of operations. An
+    /// operation failing doesn't stop the ones after it, and there's no
+    /// rolling back those before it. If any of the keys is invalid nothing
+    /// is run at all, and that's the error returned.
+    pub fn apply(&self, ops: &[Op]) -> Result<Vec<OpResult>, FskvError> {
+        for op in ops {
+            match op {
+                Op::Get(key) => self.check_key(key)?,
+                _ => self.validate_key(op.key())?,
+            }
+        }
+
+        Ok(ops
+            .iter()
+            .map(|op| {
+                let result = match op {
+                    Op::Put(key, value) => self.put(key, value).map(|_| OpResult::Done),
+                    Op::Update(key, value) => self.update(key, value).map(|_| OpResult::Done),
+                    Op::Delete(key) => self.delete(key).map(|_| OpResult::Done),
+                    Op::Get(key) => self.get(key).map(OpResult::Value),
+                };
+                result.unwrap_or_else(OpResult::Failed)
+            })
+            .collect())
+    }
+}
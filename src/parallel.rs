@@ -0,0 +1,115 @@
+use crate::{FskvError, KeyFiles, Store};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+impl Store {
+    /// `get` every key in `keys` using up to `workers` threads (at least
+    /// one), since reads are independent, blocking, I/O; one result per
+    /// key, in the same order.
+    pub fn get_many_parallel(
+        &self,
+        keys: &[&str],
+        workers: usize,
+    ) -> Vec<Result<String, FskvError>> {
+        // workers take the next key to read until there are none left
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<Result<String, FskvError>>> = Vec::with_capacity(keys.len());
+        results.resize_with(keys.len(), || None);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers.clamp(1, keys.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut read = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match keys.get(i) {
+                                Some(key) => {
+                                    let _permit = self.file_permit();
+                                    read.push((i, self.get(key)))
+                                }
+                                None => return read,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                for (i, result) in handle.join().expect("reader thread panicked") {
+                    results[i] = Some(result);
+                }
+            }
+        });
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every key is read"))
+            .collect()
+    }
+
+    /// Call `f` with every key and its value, using up to `workers` threads
+    /// (at least one), each going through whole top-level directories at a
+    /// time; e.g. to process the whole store in parallel. Keys deleted while
+    /// it runs may be skipped; on the first error the threads stop and it's
+    /// returned.
+    pub fn par_for_each(
+        &self,
+        workers: usize,
+        f: impl Fn(&str, &str) + Sync,
+    ) -> Result<(), FskvError> {
+        let mut shards = Vec::new();
+        for entry in fs::read_dir(&self.root_directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.')
+            {
+                shards.push(entry.path());
+            }
+        }
+
+        // workers take the next shard to go through until there are none
+        // left, or one of them failed
+        let next = AtomicUsize::new(0);
+        let failed = Mutex::new(None);
+        let work = || -> Result<(), FskvError> {
+            while let Some(shard) = shards.get(next.fetch_add(1, Ordering::Relaxed)) {
+                for entry in KeyFiles::at_depth(shard, 1, &self.value_extension) {
+                    if failed.lock().expect("failure lock poisoned").is_some() {
+                        return Ok(());
+                    }
+                    let (key, _) = entry?;
+                    // the value's read with a permit, but not handed to `f`
+                    // with one
+                    let value = {
+                        let _permit = self.file_permit();
+                        self.get(&key)
+                    };
+                    match value {
+                        Ok(value) => f(&key, &value),
+                        Err(e) if e.is_not_found() => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Ok(())
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..workers.clamp(1, shards.len().max(1)) {
+                scope.spawn(|| {
+                    if let Err(e) = work() {
+                        failed
+                            .lock()
+                            .expect("failure lock poisoned")
+                            .get_or_insert(e);
+                    }
+                });
+            }
+        });
+        match failed.into_inner().expect("failure lock poisoned") {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{FskvError, Store};
+use std::fs;
+#[cfg(not(target_os = "linux"))]
+use std::io;
+use std::io::ErrorKind;
+
+impl Store {
+    /// Ask for the values of `keys` to be read into the OS page cache, so
+    /// that `get`ting them later doesn't wait on the disk; e.g. at startup,
+    /// for keys known to be needed soon. On Linux that's a hint the kernel
+    /// acts on in the background, elsewhere the values are read (and
+    /// thrown away) right here. Keys that don't exist are skipped.
+    pub fn prefetch(&self, keys: &[&str]) -> Result<(), FskvError> {
+        for key in keys {
+            self.check_key(key)?;
+            let file = match self
+                .backend
+                .open(&self.key_file(key), fs::OpenOptions::new().read(true))
+            {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(FskvError::from(e)),
+            };
+            will_need(file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn will_need(file: fs::File) -> Result<(), FskvError> {
+    use std::os::unix::io::AsRawFd;
+
+    // a length of 0 means all of it
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) } {
+        0 => Ok(()),
+        errno => Err(FskvError::from(std::io::Error::from_raw_os_error(errno))),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn will_need(mut file: fs::File) -> Result<(), FskvError> {
+    io::copy(&mut file, &mut io::sink())?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use crate::FskvError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Replace the store at `live` with the one built at `staging`, e.g. to
+/// deploy a new dataset at once. The old store is moved aside next to it,
+/// to `<live>.<nanos>.backup`, and its path returned (`None` if there was no
+/// store at `live` yet); if the staged store can't be moved into place the
+/// old one is put back.
+///
+/// Both are directory renames, so they must be on the same file system.
+/// Stores already open keep using the directory they opened, wherever it
+/// was moved to, and for a moment there's no store at `live` at all.
+pub fn promote(
+    staging: impl AsRef<Path>,
+    live: impl AsRef<Path>,
+) -> Result<Option<PathBuf>, FskvError> {
+    let (staging, live) = (staging.as_ref(), live.as_ref());
+    if !fs::metadata(staging)?.is_dir() {
+        return Err(FskvError::InvalidValue(format!(
+            "{} isn't a directory",
+            staging.display()
+        )));
+    }
+
+    let backup = if live.exists() {
+        let backup = backup_path(live);
+        fs::rename(live, &backup)?;
+        Some(backup)
+    } else {
+        None
+    };
+    if let Err(e) = fs::rename(staging, live) {
+        if let Some(backup) = &backup {
+            fs::rename(backup, live)?;
+        }
+        return Err(FskvError::from(e));
+    }
+
+    Ok(backup)
+}
+
+fn backup_path(live: &Path) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("failed to get system time");
+    let mut name = live.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.backup", now.as_nanos()));
+    live.with_file_name(name)
+}
@@ -0,0 +1,113 @@
+use crate::{FskvError, Store};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+// records are appended to a value each followed by the store's delimiter,
+// `\n` unless it's configured otherwise
+impl Store {
+    /// Append `record` to the value of `key` followed by the delimiter
+    /// (see `StoreBuilder::record_delimiter`), creating the key if needed;
+    /// e.g. for values holding a log. Records can't contain the delimiter
+    /// (nor end with part of one that would then show up earlier), so
+    /// `read_records` gives them back as they were appended.
+    pub fn append_record(&self, key: &str, record: &str) -> Result<(), FskvError> {
+        self.append(key, &self.delimited(key, record)?)
+    }
+
+    /// Like `append_record`, but then dropping the oldest records so that
+    /// at most `max_records` are left, e.g. for a buffer of the latest
+    /// events. Like `append_bounded`, those that drop records replace the
+    /// value as a whole.
+    pub fn append_ringbuffer(
+        &self,
+        key: &str,
+        record: &str,
+        max_records: usize,
+    ) -> Result<(), FskvError> {
+        let delimited = self.delimited(key, record)?;
+        let _writing = self.check_key_for_write(key)?;
+        let _lock = self.lock_key(key, true)?;
+        let mut value = match self.get_bytes(key) {
+            Ok(value) => String::from_utf8(value)
+                .map_err(|_| FskvError::InvalidValue(format!("value of {:?} isn't UTF-8", key)))?,
+            Err(e) if e.is_not_found() => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        value.push_str(&delimited);
+        let ends: Vec<usize> = value
+            .match_indices(self.record_delimiter.as_str())
+            .map(|(i, delimiter)| i + delimiter.len())
+            .collect();
+        match ends.len().checked_sub(max_records + 1) {
+            // the oldest records to keep start after the end of this one
+            Some(last_dropped) => self.write_locked(key, &value[ends[last_dropped]..]),
+            None => self.append_locked(key, &delimited),
+        }
+    }
+
+    /// Iterate over the records of a value written with `append_record`,
+    /// without the delimiters and without loading all of it. What follows
+    /// the last delimiter, if anything, is a record too.
+    pub fn read_records(
+        &self,
+        key: &str,
+    ) -> Result<impl Iterator<Item = Result<String, FskvError>>, FskvError> {
+        self.check_key(key)?;
+        Ok(Records {
+            reader: BufReader::new(self.value_reader(key, self.open_value(key)?)?),
+            delimiter: self.record_delimiter.as_bytes().to_vec(),
+            key: key.to_string(),
+        })
+    }
+
+    // `record` followed by the delimiter, if it can be read back as it is
+    fn delimited(&self, key: &str, record: &str) -> Result<String, FskvError> {
+        let delimited = format!("{}{}", record, self.record_delimiter);
+        match delimited.find(self.record_delimiter.as_str()) == Some(record.len()) {
+            true => Ok(delimited),
+            false => Err(FskvError::InvalidValue(format!(
+                "record for {:?} contains the delimiter {:?}",
+                key, self.record_delimiter
+            ))),
+        }
+    }
+}
+
+struct Records {
+    reader: BufReader<Box<dyn Read + Send>>,
+    delimiter: Vec<u8>,
+    key: String,
+}
+
+impl Records {
+    fn next_record(&mut self) -> Result<Option<String>, FskvError> {
+        // the delimiter is never empty
+        let last = self.delimiter[self.delimiter.len() - 1];
+        let mut record = Vec::new();
+        loop {
+            if self.reader.read_until(last, &mut record)? == 0 {
+                if record.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+            if record.ends_with(&self.delimiter) {
+                record.truncate(record.len() - self.delimiter.len());
+                break;
+            }
+        }
+
+        String::from_utf8(record)
+            .map(Some)
+            .map_err(|_| FskvError::InvalidValue(format!("record of {:?} isn't UTF-8", self.key)))
+    }
+}
+
+impl Iterator for Records {
+    type Item = Result<String, FskvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::checksum::CHECKSUM_SIDECARS;
+use crate::count::COUNT_FILE;
+use crate::lock::STORE_LOCK_FILE;
+use crate::marker::MARKER_FILE;
+use crate::usage::USAGE_FILE;
+use crate::variants::VARIANT_SIDECARS;
+use crate::{
+    FskvError, Store, CREATED_SIDECAR, DIRECTORY_TREE_HEIGHT, LOCK_SIDECAR, VERSION_SIDECAR,
+};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// the kinds of sidecar about a key's value, which go wherever it goes
+fn value_sidecars() -> impl Iterator<Item = &'static str> {
+    [VERSION_SIDECAR, CREATED_SIDECAR]
+        .iter()
+        .chain(CHECKSUM_SIDECARS.iter())
+        .chain(VARIANT_SIDECARS.iter())
+        .copied()
+}
+
+/// What `Store::reconcile` found in the wrong buckets, as (key, path of the
+/// file) pairs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Files `get` can't find, since the key isn't where it belongs;
+    /// `Store::repair_placement` moves these back
+    pub misplaced: Vec<(String, PathBuf)>,
+    /// Stale copies of keys that are also where they belong, which `get`
+    /// never reads and `repair_placement` leaves alone
+    pub duplicates: Vec<(String, PathBuf)>,
+}
+
+impl Store {
+    /// Move every key file that's in the wrong bucket, where `get` can't find
+    /// it (e.g. after files were moved around by an external tool), back to
+    /// where it belongs, and return how many were moved. Keys that already
+    /// exist in their right bucket are left alone on both sides.
+    pub fn repair_placement(&self) -> Result<usize, FskvError> {
+        // moving files while walking the tree could visit them twice
+        let files = self.key_files().collect::<Result<Vec<_>, _>>()?;
+
+        let mut moved = 0;
+        for (key, path) in files {
+            if self.check_key(&key).is_err() {
+                continue;
+            }
+            let bucket = self.get_key_path(&key);
+            if path.parent() == Some(bucket.as_path()) {
+                continue;
+            }
+
+            let _lock = self.lock_key(&key, true)?;
+            let right_path = self.key_file(&key);
+            if right_path.exists() {
+                continue;
+            }
+            fs::rename(&path, &right_path)?;
+            // its sidecars go along with it; moving keeps the file's size
+            // and mtime, so checksums and compressed copies still match
+            for kind in value_sidecars() {
+                let sidecar = path.with_file_name(format!(".{}.{}", key, kind));
+                match fs::rename(&sidecar, self.sidecar_path(&key, kind)) {
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                    r => r?,
+                }
+            }
+            moved += 1;
+        }
+
+        Ok(moved)
+    }
+
+    /// Find the files in the wrong buckets, e.g. halfway through moving
+    /// keys to a different layout, without changing anything, for someone
+    /// to review.
+    pub fn reconcile(&self) -> Result<ReconcileReport, FskvError> {
+        let mut report = ReconcileReport::default();
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            if self.check_key(&key).is_err() {
+                continue;
+            }
+            let right_path = self.key_file(&key);
+            if path == right_path {
+                continue;
+            }
+
+            match right_path.exists() {
+                true => report.duplicates.push((key, path)),
+                false => report.misplaced.push((key, path)),
+            }
+        }
+
+        report.misplaced.sort();
+        report.duplicates.sort();
+        Ok(report)
+    }
+
+    /// Find the sidecar files (versions, checksums, locks and the like)
+    /// whose key's file is gone and, in versioned stores, the key files
+    /// missing their version, e.g. after a crash halfway through a write.
+    /// With `repair` the orphaned sidecars are removed too, along with the
+    /// locks taken to remove them; the key files are only reported,
+    /// removing them would lose their values.
+    pub fn find_orphans(&self, repair: bool) -> Result<Vec<PathBuf>, FskvError> {
+        let mut orphans = Vec::new();
+        let mut dirs = vec![(self.root_directory.clone(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            // listed up front, so the locks repairing takes aren't found
+            let entries = fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if depth < DIRECTORY_TREE_HEIGHT {
+                    if entry.file_type()?.is_dir() && !name.starts_with('.') {
+                        dirs.push((entry.path(), depth + 1));
+                    }
+                    continue;
+                }
+
+                // sidecars are named `.<key>.<kind>`
+                let sidecar = name
+                    .strip_prefix('.')
+                    .and_then(|name| name.rsplit_once('.'))
+                    .filter(|(_, kind)| {
+                        *kind == LOCK_SIDECAR || value_sidecars().any(|k| k == *kind)
+                    });
+                match sidecar {
+                    Some((key, kind)) => {
+                        let key_file = dir.join(format!("{}{}", key, self.value_extension));
+                        if key_file.exists() {
+                            continue;
+                        }
+                        if repair {
+                            // unless a write put the key back meanwhile
+                            let lock = self.lock_key(key, true)?;
+                            if !key_file.exists() {
+                                if kind != LOCK_SIDECAR {
+                                    match fs::remove_file(entry.path()) {
+                                        Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                                        r => r?,
+                                    }
+                                }
+                                self.remove_lock(key, lock)?;
+                            }
+                        }
+                        orphans.push(entry.path());
+                    }
+                    None if self.versioned && !name.starts_with('.') => {
+                        let key = match name.strip_suffix(self.value_extension.as_str()) {
+                            Some(key) if !key.is_empty() => key,
+                            _ => continue,
+                        };
+                        if !dir.join(format!(".{}.{}", key, VERSION_SIDECAR)).exists() {
+                            orphans.push(entry.path());
+                        }
+                    }
+                    None => (),
+                }
+            }
+        }
+
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Find the files that are neither keys nor the store's own, by their
+    /// names alone: in the buckets, files that aren't named after a valid
+    /// key (and the value extension, if any) or one of their sidecars, like
+    /// temporary files left behind by interrupted writes; and anything
+    /// other than directories and the store's files above them. Nothing is
+    /// read or changed, it's for someone to look into.
+    pub fn audit_filenames(&self) -> Result<Vec<PathBuf>, FskvError> {
+        let mut foreign = Vec::new();
+        let mut dirs = vec![(self.root_directory.clone(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    // keys are always valid UTF-8
+                    Err(_) => {
+                        foreign.push(entry.path());
+                        continue;
+                    }
+                };
+
+                let known = if depth < DIRECTORY_TREE_HEIGHT {
+                    if file_type.is_dir() && !name.starts_with('.') {
+                        dirs.push((entry.path(), depth + 1));
+                        continue;
+                    }
+                    depth == 0
+                        && file_type.is_file()
+                        && [MARKER_FILE, COUNT_FILE, STORE_LOCK_FILE, USAGE_FILE]
+                            .contains(&name.as_str())
+                } else if !file_type.is_file() {
+                    false
+                } else if let Some(sidecar) = name.strip_prefix('.') {
+                    // sidecars are named `.<key>.<kind>`
+                    sidecar.rsplit_once('.').is_some_and(|(key, kind)| {
+                        ([VERSION_SIDECAR, CREATED_SIDECAR, LOCK_SIDECAR].contains(&kind)
+                            || CHECKSUM_SIDECARS.contains(&kind)
+                            || VARIANT_SIDECARS.contains(&kind))
+                            && self.check_key(key).is_ok()
+                    })
+                } else {
+                    name.strip_suffix(self.value_extension.as_str())
+                        .is_some_and(|key| self.validate_key(key).is_ok())
+                };
+                if !known {
+                    foreign.push(entry.path());
+                }
+            }
+        }
+
+        foreign.sort();
+        Ok(foreign)
+    }
+}
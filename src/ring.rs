@@ -0,0 +1,55 @@
+use std::fmt;
+
+// how many points on the ring each bucket gets; the more there are, the
+// more evenly keys spread over the buckets, but every one of them is hashed
+// and kept in memory (16 bytes) while the store is open
+const VIRTUAL_NODES: u32 = 64;
+// the most buckets a ring can have, each named by one directory; that's
+// 256K points, 4 MB, at most
+pub(crate) const MAX_RING_BUCKETS: u32 = 1 << 12;
+
+// buckets placed on a hash ring, as laid out by `StoreBuilder::ring_buckets`:
+// each key goes to the bucket of the first point at or after its hash, so
+// adding a bucket only takes over the keys that land just before its own
+// points, about one in as many buckets as there are
+pub(crate) struct Ring {
+    // sorted by position on the ring
+    points: Vec<(u64, u32)>,
+    buckets: u32,
+}
+
+impl Ring {
+    pub(crate) fn new(buckets: u32) -> Ring {
+        let mut points = Vec::with_capacity((buckets * VIRTUAL_NODES) as usize);
+        for bucket in 0..buckets {
+            for node in 0..VIRTUAL_NODES {
+                let digest = md5::compute(format!("{}:{}", bucket, node));
+                points.push((position(&digest.0), bucket));
+            }
+        }
+        points.sort_unstable();
+        Ring { points, buckets }
+    }
+
+    // the bucket of a key whose hash is `digest`
+    pub(crate) fn bucket(&self, digest: &[u8; 16]) -> u32 {
+        let position = position(digest);
+        let i = self.points.partition_point(|&(point, _)| point < position);
+        // past the last point it wraps around to the first one
+        self.points[i % self.points.len()].1
+    }
+}
+
+impl fmt::Debug for Ring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ring")
+            .field("buckets", &self.buckets)
+            .finish()
+    }
+}
+
+fn position(digest: &[u8; 16]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
@@ -0,0 +1,180 @@
+use crate::{FskvError, Store};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+impl Store {
+    /// Every key in the store, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = Result<String, FskvError>> {
+        self.key_files()
+            .map(|entry| entry.map(|(key, _)| key).map_err(FskvError::from))
+    }
+
+    /// Every key in the store along with the path to its file, in no
+    /// particular order, e.g. for a list of files to hand to other tools.
+    /// Paths are absolute, starting with the store's root directory.
+    pub fn key_paths(&self) -> impl Iterator<Item = Result<(String, PathBuf), FskvError>> {
+        self.key_files().map(|entry| entry.map_err(FskvError::from))
+    }
+
+    /// Every key in the store at one point in time, in order, which `keys`
+    /// doesn't promise while there are concurrent writes, for stores created
+    /// with `StoreBuilder::snapshots`. Writes (from any process) wait for
+    /// it to be done, so it pauses them for as long as listing all the
+    /// keys takes.
+    pub fn snapshot_keys(&self) -> Result<Vec<String>, FskvError> {
+        if !self.snapshots {
+            return Err(FskvError::ConfigMismatch(
+                "store doesn't take snapshots".to_string(),
+            ));
+        }
+        let _lock = self.lock_store(true)?;
+        let mut keys = self.keys().collect::<Result<Vec<_>, _>>()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// The `n` most recently modified keys along with their modification
+    /// time, newest first.
+    pub fn recent(&self, n: usize) -> Result<Vec<(String, SystemTime)>, FskvError> {
+        // keep a min-heap of the n newest seen so far, so the oldest of
+        // them is always the one to go
+        let mut newest = BinaryHeap::with_capacity(n + 1);
+
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            let mtime = fs::metadata(&path)?.modified()?;
+            newest.push(Reverse((mtime, key)));
+            if newest.len() > n {
+                newest.pop();
+            }
+        }
+
+        // ascending order of Reverse is newest first
+        Ok(newest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((mtime, key))| (key, mtime))
+            .collect())
+    }
+
+    /// Keys whose value contains `needle`, stopping once `max_results` have
+    /// been found (if given). This reads every value in the store, so it's
+    /// only reasonable for small stores.
+    pub fn search_values(
+        &self,
+        needle: &str,
+        max_results: Option<usize>,
+    ) -> Result<Vec<String>, FskvError> {
+        let mut found = Vec::new();
+
+        for entry in self.key_files() {
+            if max_results.is_some_and(|max| found.len() >= max) {
+                break;
+            }
+            let (key, _) = entry?;
+            let value = match self.get(&key) {
+                Ok(value) => value,
+                // deleted since it was listed, or empty and taken as absent
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+            if value.contains(needle) {
+                found.push(key);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Keys last modified within `[start, end)`, e.g. to process a store in
+    /// time buckets.
+    pub fn keys_between(
+        &self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> impl Iterator<Item = Result<String, FskvError>> {
+        self.key_files().filter_map(move |entry| {
+            let modified = entry.and_then(|(key, path)| Ok((key, fs::metadata(path)?.modified()?)));
+            match modified {
+                Ok((key, mtime)) if mtime >= start && mtime < end => Some(Ok(key)),
+                Ok(_) => None,
+                Err(e) => Some(Err(FskvError::from(e))),
+            }
+        })
+    }
+
+    /// The values of every key starting with `prefix`, in key order, joined
+    /// with `separator`; e.g. to reassemble data stored in chunks under
+    /// `part:0`, `part:1` and so on.
+    pub fn concat_prefix(&self, prefix: &str, separator: &str) -> Result<String, FskvError> {
+        let mut keys = Vec::new();
+        for entry in self.key_files() {
+            let (key, _) = entry?;
+            if key.starts_with(prefix) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            match self.get(&key) {
+                Ok(value) => values.push(value),
+                // deleted since it was listed, or empty and taken as absent
+                Err(e) if e.is_not_found() => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(values.join(separator))
+    }
+
+    /// Every key along with the size of its value, smallest first or, unless
+    /// `ascending`, largest first; e.g. to find the values worth moving to
+    /// cheaper storage. The sizes are all looked up (without reading any
+    /// values) before the first one is returned, so keys written meanwhile
+    /// may be missing or have changed size.
+    pub fn iter_by_size(
+        &self,
+        ascending: bool,
+    ) -> Result<impl Iterator<Item = Result<(String, u64), FskvError>>, FskvError> {
+        let mut sizes = Vec::new();
+        for entry in self.key_files() {
+            let (key, path) = entry?;
+            match fs::metadata(path) {
+                Ok(metadata) => sizes.push((key, metadata.len())),
+                // deleted since it was listed
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(FskvError::from(e)),
+            }
+        }
+
+        // keys of the same size are always in key order
+        sizes.sort_by(|(a, a_len), (b, b_len)| match ascending {
+            true => a_len.cmp(b_len).then_with(|| a.cmp(b)),
+            false => b_len.cmp(a_len).then_with(|| a.cmp(b)),
+        });
+        Ok(sizes.into_iter().map(Ok))
+    }
+
+    /// How many keys each top-level directory (shard) holds, to check keys
+    /// are spread evenly, or how partitioned keys are spread over shards.
+    pub fn shard_counts(&self) -> Result<HashMap<String, usize>, FskvError> {
+        let mut counts = HashMap::new();
+        for entry in self.key_files() {
+            let (_, path) = entry?;
+            let shard = path
+                .strip_prefix(&self.root_directory)
+                .ok()
+                .and_then(|relative| relative.iter().next())
+                .map(|shard| shard.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            *counts.entry(shard).or_insert(0) += 1;
+        }
+
+        Ok(counts)
+    }
+}
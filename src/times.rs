@@ -0,0 +1,76 @@
+use crate::{write_atomically, FskvError, Store, CREATED_SIDECAR};
+use std::fs;
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+
+// creation times are kept next to each key, in nanoseconds since the epoch,
+// by stores built with `StoreBuilder::creation_times`
+impl Store {
+    /// When `key` was created, by a put or by the first update or append,
+    /// which later writes don't change; a renamed key keeps its own. Fails
+    /// with `FskvError::InvalidValue` if that wasn't recorded, i.e. the key
+    /// was created without `StoreBuilder::creation_times`.
+    pub fn created_at(&self, key: &str) -> Result<SystemTime, FskvError> {
+        self.check_key(key)?;
+        // a creation time only makes sense while the key exists
+        self.key_metadata(key)?;
+        let created = match fs::read_to_string(self.sidecar_path(key, CREATED_SIDECAR)) {
+            Ok(created) => created,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                return Err(FskvError::InvalidValue(format!(
+                    "no creation time recorded for {:?}",
+                    key
+                )))
+            }
+            Err(e) => return Err(FskvError::from(e)),
+        };
+        match created.trim().parse::<u64>() {
+            Ok(nanos) => Ok(SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)),
+            Err(_) => Err(FskvError::InvalidValue(format!(
+                "creation time of {:?} is corrupted: {:?}",
+                key, created
+            ))),
+        }
+    }
+
+    /// When `key` was last written: put or updated.
+    pub fn modified_at(&self, key: &str) -> Result<SystemTime, FskvError> {
+        self.check_key(key)?;
+        Ok(self.key_metadata(key)?.modified()?)
+    }
+
+    fn key_metadata(&self, key: &str) -> Result<fs::Metadata, FskvError> {
+        match self.backend.metadata(&self.key_file(key)) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                Err(FskvError::KeyNotFound(key.to_string()))
+            }
+            r => r.map_err(FskvError::from),
+        }
+    }
+
+    // have `to`, which now holds the value of `from`, keep its creation
+    // time, or none if it has none
+    pub(crate) fn move_created(&self, from: &str, to: &str) -> Result<(), FskvError> {
+        match fs::rename(
+            self.sidecar_path(from, CREATED_SIDECAR),
+            self.sidecar_path(to, CREATED_SIDECAR),
+        ) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                self.remove_sidecar(to, CREATED_SIDECAR)
+            }
+            r => r.map_err(FskvError::from),
+        }
+    }
+
+    pub(crate) fn write_created(&self, key: &str) -> Result<(), FskvError> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("failed to get system time")
+            .as_nanos();
+        write_atomically(
+            &self.sidecar_path(key, CREATED_SIDECAR),
+            now.to_string().as_bytes(),
+        )
+        .map_err(FskvError::from)
+    }
+}
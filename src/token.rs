@@ -0,0 +1,68 @@
+use crate::checksum::mtime_nanos;
+use crate::{FskvError, Store};
+use std::io::prelude::*;
+
+/// What a value was when it was written, for `Store::get_validated` to
+/// tell whether it changed since: its file's modification time and the
+/// CRC32 of its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VersionToken {
+    mtime: u128,
+    checksum: u32,
+}
+
+/// What `Store::get_validated` found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GetResult {
+    /// The value is still the one the token is for
+    Unchanged,
+    /// The value changed since, to this
+    Modified(String),
+}
+
+impl Store {
+    /// Like `put`, but also returning a token for the value written, for
+    /// `get_validated`.
+    pub fn put_with_token(&self, key: &str, value: &str) -> Result<VersionToken, FskvError> {
+        self.put(key, value)?;
+        self.token_for(key, value)
+    }
+
+    /// Like `update`, but also returning a token for the value written, for
+    /// `get_validated`.
+    pub fn update_with_token(&self, key: &str, value: &str) -> Result<VersionToken, FskvError> {
+        self.update(key, value)?;
+        self.token_for(key, value)
+    }
+
+    /// Whether the value of `key` is still the one `token` is for, or what
+    /// it is now otherwise; e.g. for a client caching what it wrote to tell
+    /// whether its copy is stale. A write landing right after the one the
+    /// token is for may make it look modified when it isn't, never the
+    /// other way around.
+    pub fn get_validated(&self, key: &str, token: VersionToken) -> Result<GetResult, FskvError> {
+        self.check_key(key)?;
+        let file = self.open_value(key)?;
+        let mtime = mtime_nanos(&file.metadata()?);
+        let mut bytes = Vec::new();
+        self.value_reader(key, file)?.read_to_end(&mut bytes)?;
+
+        let current = VersionToken {
+            mtime,
+            checksum: crc32fast::hash(&bytes),
+        };
+        match current == token {
+            true => Ok(GetResult::Unchanged),
+            false => self.decode_value(bytes).map(GetResult::Modified),
+        }
+    }
+
+    // the token of `value`, just written to `key`
+    fn token_for(&self, key: &str, value: &str) -> Result<VersionToken, FskvError> {
+        let metadata = self.open_value(key)?.metadata()?;
+        Ok(VersionToken {
+            mtime: mtime_nanos(&metadata),
+            checksum: crc32fast::hash(value.as_bytes()),
+        })
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{FskvError, Store};
+use std::fs;
+use std::io::prelude::*;
+use std::io::{ErrorKind, SeekFrom};
+
+// the file at the root of a store keeping the disk usage last reported by
+// `disk_usage_delta`
+pub(crate) const USAGE_FILE: &str = ".fskv.usage";
+
+impl Store {
+    /// How many bytes the values of all the keys take, not counting the
+    /// store's own files or the space lost to file system blocks.
+    pub fn disk_usage(&self) -> Result<u64, FskvError> {
+        let mut usage = 0;
+        for entry in self.key_files() {
+            let (_, path) = entry?;
+            match fs::metadata(path) {
+                Ok(metadata) => usage += metadata.len(),
+                // deleted since it was listed
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(FskvError::from(e)),
+            }
+        }
+        Ok(usage)
+    }
+
+    /// How much `disk_usage` changed since this was last called (from any
+    /// process), e.g. for a monitor to tell how fast the store grows
+    /// without keeping track of it itself; the first call counts from 0.
+    /// The usage it saw is kept in a file at the store's root for the next
+    /// call to compare against.
+    pub fn disk_usage_delta(&self) -> Result<i64, FskvError> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root_directory.join(USAGE_FILE))?;
+        // so concurrent calls each get a delta of their own
+        file.lock()?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        // an empty file is a usage that was never written
+        let previous: u64 = match contents.trim() {
+            "" => 0,
+            n => n.parse().map_err(|_| {
+                FskvError::CorruptStore(format!("malformed disk usage: {:?}", contents))
+            })?,
+        };
+
+        let usage = self.disk_usage()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(usage.to_string().as_bytes())?;
+        Ok(usage as i64 - previous as i64)
+    }
+}
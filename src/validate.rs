@@ -0,0 +1,54 @@
+use crate::count::COUNT_FILE;
+use crate::lock::STORE_LOCK_FILE;
+use crate::marker::MARKER_FILE;
+use crate::usage::USAGE_FILE;
+use crate::{FskvError, DIRECTORY_TREE_HEIGHT};
+use std::fs;
+use std::path::Path;
+
+// a quick look for signs of a corrupt store, without reading any values:
+// the root holds only directories (and the store's own files), it's directories all
+// the way down to the buckets, buckets hold only files, and there are no
+// temporary files left behind by interrupted writes
+pub(crate) fn validate(root: &Path) -> Result<(), FskvError> {
+    if !fs::metadata(root)?.is_dir() {
+        return Err(corrupt(root, "not a directory"));
+    }
+    match fs::metadata(root.join(MARKER_FILE)) {
+        Ok(metadata) if !metadata.is_file() => {
+            return Err(corrupt(&root.join(MARKER_FILE), "marker isn't a file"))
+        }
+        _ => (),
+    }
+
+    let mut dirs = vec![(root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+
+            if name.starts_with('.') && name.ends_with(".tmp") {
+                return Err(corrupt(&path, "leftover temporary file"));
+            } else if depth < DIRECTORY_TREE_HEIGHT {
+                if file_type.is_dir() {
+                    dirs.push((path, depth + 1));
+                } else if !(depth == 0
+                    && [MARKER_FILE, COUNT_FILE, STORE_LOCK_FILE, USAGE_FILE]
+                        .contains(&name.as_str()))
+                {
+                    return Err(corrupt(&path, "file above the bucket level"));
+                }
+            } else if !file_type.is_file() {
+                return Err(corrupt(&path, "not a file, inside a bucket"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn corrupt(path: &Path, reason: &str) -> FskvError {
+    FskvError::CorruptStore(format!("{}: {}", path.display(), reason))
+}
@@ -0,0 +1,135 @@
+use crate::checksum::mtime_nanos;
+use crate::{temp_file_name, write_atomically, FskvError, Store};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+
+// the kinds of sidecar holding a compressed copy of a value, one per
+// encoding, each starting with a line identifying the file it's a copy of
+// (as for checksums), so it's not served for whatever replaces it
+pub(crate) const VARIANT_SIDECARS: [&str; 3] = ["gz", "br", "zst"];
+
+/// The compressed copies of a value `Store::put_with_variants` keeps, and
+/// the codecs `Store::put_with_codec` compresses values with, named as in
+/// HTTP's `Content-Encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    /// gzip
+    Gzip,
+    /// Brotli, at its highest quality
+    Brotli,
+    /// Zstandard, at its highest level short of the memory hungry ones
+    Zstd,
+}
+
+impl ContentEncoding {
+    fn sidecar(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => VARIANT_SIDECARS[0],
+            ContentEncoding::Brotli => VARIANT_SIDECARS[1],
+            ContentEncoding::Zstd => VARIANT_SIDECARS[2],
+        }
+    }
+
+    pub(crate) fn compress(self, value: &[u8]) -> Result<Vec<u8>, FskvError> {
+        match self {
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(value)?;
+                Ok(encoder.finish()?)
+            }
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+                encoder.write_all(value)?;
+                Ok(encoder.into_inner())
+            }
+            ContentEncoding::Zstd => Ok(zstd::stream::encode_all(value, 19)?),
+        }
+    }
+}
+
+impl Store {
+    /// Like `put`, but also keeping the value compressed with every
+    /// `ContentEncoding`, for `get_variant`; e.g. for static assets served
+    /// over HTTP, which can then be sent as they are instead of compressed
+    /// on every request. The copies are only good until the value is next
+    /// written, by any means.
+    pub fn put_with_variants(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        let key_path = self.get_key_path(key);
+        self.create_bucket(&key_path)?;
+        let _lock = match self.versioned {
+            true => Some(self.lock_key(key, true)?),
+            false => None,
+        };
+
+        // the copies identify the file written here, which is linked into
+        // place as it is
+        let tmp_file = key_path.join(temp_file_name());
+        let stored = self.encode_value(value.as_bytes(), None)?;
+        let metadata = self
+            .in_bucket(&key_path, || self.value_file_options().open(&tmp_file))
+            .and_then(|mut f| f.write_all(&stored).and_then(|_| f.metadata()));
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                fs::remove_file(&tmp_file).unwrap_or(());
+                return Err(FskvError::from(e));
+            }
+        };
+        self.link_new_value(key, &tmp_file)?;
+
+        let header = format!("{} {}\n", metadata.len(), mtime_nanos(&metadata));
+        let encodings = [
+            ContentEncoding::Gzip,
+            ContentEncoding::Brotli,
+            ContentEncoding::Zstd,
+        ];
+        for encoding in encodings.iter() {
+            let mut variant = header.clone().into_bytes();
+            variant.extend(encoding.compress(value.as_bytes())?);
+            write_atomically(&self.sidecar_path(key, encoding.sidecar()), &variant)?;
+        }
+        self.finish_put(key)
+    }
+
+    // remove the compressed copies of a value that's gone, which would
+    // otherwise be left behind as orphans
+    pub(crate) fn remove_variants(&self, key: &str) -> Result<(), FskvError> {
+        for kind in VARIANT_SIDECARS.iter() {
+            self.remove_sidecar(key, kind)?;
+        }
+        Ok(())
+    }
+
+    /// The value of `key` compressed with `encoding`, as kept by
+    /// `put_with_variants`, or `None` if it wasn't, or the value has been
+    /// written since.
+    pub fn get_variant(
+        &self,
+        key: &str,
+        encoding: ContentEncoding,
+    ) -> Result<Option<Vec<u8>>, FskvError> {
+        self.check_key(key)?;
+        let metadata = match fs::metadata(self.key_file(key)) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                return Err(FskvError::KeyNotFound(key.to_string()))
+            }
+            Err(e) => return Err(FskvError::from(e)),
+        };
+        let mut variant = match fs::read(self.sidecar_path(key, encoding.sidecar())) {
+            Ok(variant) => variant,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FskvError::from(e)),
+        };
+
+        let expected = format!("{} {}\n", metadata.len(), mtime_nanos(&metadata));
+        match variant.starts_with(expected.as_bytes()) {
+            true => Ok(Some(variant.split_off(expected.len()))),
+            false => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{write_atomically, FskvError, Store, VERSION_SIDECAR};
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+impl Store {
+    /// The current version of `key` in a store created with
+    /// `StoreBuilder::versioned`: 1 once it's put, plus one per update.
+    pub fn version_of(&self, key: &str) -> Result<u64, FskvError> {
+        self.check_key(key)?;
+        self.check_versioned()?;
+        // a version only makes sense while the key exists
+        fs::metadata(self.key_file(key))?;
+        self.read_version(key)
+    }
+
+    /// Like `update`, but only if `key` is still at `expected_version` (0
+    /// when it doesn't exist), returning the new version. Otherwise it fails
+    /// with `FskvError::VersionMismatch` and the value is left alone.
+    pub fn update_with_version(
+        &self,
+        key: &str,
+        value: &str,
+        expected_version: u64,
+    ) -> Result<u64, FskvError> {
+        let _writing = self.check_key_for_write(key)?;
+        self.check_versioned()?;
+        self.update_versioned(key, value, Some(expected_version))
+    }
+
+    pub(crate) fn update_versioned(
+        &self,
+        key: &str,
+        value: &str,
+        expected_version: Option<u64>,
+    ) -> Result<u64, FskvError> {
+        let _lock = self.lock_key(key, true)?;
+        let current = self.read_version(key)?;
+        match expected_version {
+            Some(expected) if expected != current => Err(FskvError::VersionMismatch {
+                expected,
+                actual: current,
+            }),
+            _ => {
+                // the version goes first: should we crash in between,
+                // clients holding the old one are still turned away
+                self.write_version(key, current + 1)?;
+                self.replace_value(key, value)?;
+                Ok(current + 1)
+            }
+        }
+    }
+
+    // write the value of a key whose lock is already held, bumping its
+    // version if the store keeps them
+    pub(crate) fn write_locked(&self, key: &str, value: &str) -> Result<(), FskvError> {
+        self.bump_version(key)?;
+        self.replace_value(key, value)
+    }
+
+    // note that a locked key is about to change, if the store keeps versions
+    pub(crate) fn bump_version(&self, key: &str) -> Result<(), FskvError> {
+        if self.versioned {
+            let current = self.read_version(key)?;
+            self.write_version(key, current + 1)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_version(&self, key: &str) -> Result<u64, FskvError> {
+        match fs::read_to_string(self.sidecar_path(key, VERSION_SIDECAR)) {
+            Ok(version) => version.trim().parse().map_err(|e| {
+                FskvError::from(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("corrupt version for key {:?}: {}", key, e),
+                ))
+            }),
+            // never written
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(FskvError::from(e)),
+        }
+    }
+
+    pub(crate) fn write_version(&self, key: &str, version: u64) -> Result<(), FskvError> {
+        write_atomically(
+            &self.sidecar_path(key, VERSION_SIDECAR),
+            version.to_string().as_bytes(),
+        )
+        .map_err(FskvError::from)
+    }
+
+    fn check_versioned(&self) -> Result<(), FskvError> {
+        match self.versioned {
+            true => Ok(()),
+            false => Err(FskvError::ConfigMismatch(
+                "store doesn't keep versions".to_string(),
+            )),
+        }
+    }
+}
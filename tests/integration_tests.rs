@@ -1,5 +1,19 @@
-use fskv::Store;
+#![allow(clippy::bool_assert_comparison)]
+
+use fskv::{
+    promote, Backend, ChecksumAlgorithm, ContentEncoding, DedupStats, Diff, Encoding, FsBackend,
+    FskvError, GetResult, KeepEnd, MutationEvent, MutationKind, Op, OpKind, OpResult, Percentiles,
+    ReconcileReport, Store, StoreBuilder,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::vec::Vec;
 
 fn teardown(directories: Vec<&str>) {
@@ -46,7 +60,3317 @@ fn test_delete() {
     assert_eq!(ds.delete("delkey").is_ok(), true);
     assert_eq!(ds.get("delkey").is_ok(), false);
     // does not exist, should fail
-    assert_eq!(ds.delete("delkey_does_not_exist").is_ok(), false);
+    match ds.delete("delkey_does_not_exist") {
+        Err(FskvError::KeyNotFound(key)) => assert_eq!(key, "delkey_does_not_exist"),
+        r => panic!("unexpected result: {:?}", r),
+    }
 
     teardown(vec!["fskv_test"]);
 }
+
+#[test]
+fn test_put_batch() {
+    let ds = Store::new("fskv_test_put_batch", true);
+    assert_eq!(ds.is_ok(), true);
+    let ds = ds.unwrap();
+    assert_eq!(
+        ds.put_batch(&[("batch1", "a"), ("batch2", "b")]).is_ok(),
+        true
+    );
+    assert_eq!(ds.get("batch2").unwrap(), "b");
+    // batch3 is written and then rolled back since batch1 already exists
+    assert_eq!(
+        ds.put_batch(&[("batch3", "c"), ("batch1", "a")]).is_ok(),
+        false
+    );
+    assert_eq!(ds.get("batch3").is_ok(), false);
+    assert_eq!(ds.get("batch1").unwrap(), "a");
+
+    teardown(vec!["fskv_test_put_batch"]);
+}
+
+#[test]
+fn test_put_batch_with_retries() {
+    let backend = Arc::new(FaultyBackend::default());
+    let ds = StoreBuilder::new("fskv_test_put_batch_with_retries")
+        .create(true)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    // fail creating the directory for the first entry, twice
+    backend.interrupted_dirs.store(2, Ordering::SeqCst);
+    let results = ds.put_batch_with_retries(&[("a", "1"), ("b", "2")], 3);
+    assert_eq!(results.iter().all(|r| r.is_ok()), true);
+    assert_eq!(ds.get("a").unwrap(), "1");
+    assert_eq!(ds.get("b").unwrap(), "2");
+
+    // without enough retries only that entry fails
+    backend.interrupted_dirs.store(2, Ordering::SeqCst);
+    let results = ds.put_batch_with_retries(&[("c", "3"), ("d", "4")], 1);
+    assert_eq!(results[0].is_ok(), false);
+    assert_eq!(results[1].is_ok(), true);
+    assert_eq!(ds.get("c").is_ok(), false);
+    assert_eq!(ds.get("d").unwrap(), "4");
+
+    teardown(vec!["fskv_test_put_batch_with_retries"]);
+}
+
+#[test]
+fn test_export_import_tar() {
+    let ds = Store::new("fskv_test_export_tar", true).unwrap();
+    let entries = [("tar1", "one"), ("tar2", "two"), ("tar3", "")];
+    for (k, v) in entries.iter() {
+        assert_eq!(ds.put(k, v).is_ok(), true);
+    }
+    let mut buffer = Vec::new();
+    assert_eq!(ds.export_tar(&mut buffer).is_ok(), true);
+
+    // the archive is a plain tar stream of key -> value
+    let mut exported = Vec::new();
+    let mut archive = tar::Archive::new(&buffer[..]);
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let key = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut value = String::new();
+        entry.read_to_string(&mut value).unwrap();
+        exported.push((key, value));
+    }
+    exported.sort();
+    let expected: Vec<(String, String)> = entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assert_eq!(exported, expected);
+
+    // and it can be restored into a different store
+    let fresh = Store::new("fskv_test_import_tar", true).unwrap();
+    assert_eq!(fresh.import_tar(&buffer[..]).is_ok(), true);
+    for (k, v) in entries.iter() {
+        assert_eq!(fresh.get(k).unwrap(), *v);
+    }
+    // importing again collides with the existing keys
+    assert_eq!(fresh.import_tar(&buffer[..]).is_ok(), false);
+
+    teardown(vec!["fskv_test_export_tar", "fskv_test_import_tar"]);
+}
+
+#[test]
+fn test_key_validator() {
+    let ds = StoreBuilder::new("fskv_test_key_validator")
+        .create(true)
+        .key_validator(|key| {
+            if key.contains(' ') {
+                Err(format!("{:?} contains spaces", key))
+            } else {
+                Ok(())
+            }
+        })
+        .build()
+        .unwrap();
+    match ds.put("has spaces", "foo") {
+        Err(FskvError::InvalidKey(msg)) => assert_eq!(msg, "\"has spaces\" contains spaces"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.update("has spaces", "foo").is_ok(), false);
+    assert_eq!(ds.put("no_spaces", "foo").is_ok(), true);
+    assert_eq!(ds.get("no_spaces").unwrap(), "foo");
+    // the built-in checks still apply
+    match ds.put("../escape", "foo") {
+        Err(FskvError::InvalidKey(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_key_validator"]);
+}
+
+#[test]
+fn test_recent() {
+    let ds = Store::new("fskv_test_recent", true).unwrap();
+    for key in ["recent1", "recent2", "recent3", "recent4"].iter() {
+        assert_eq!(ds.put(key, "foo").is_ok(), true);
+        thread::sleep(Duration::from_millis(20));
+    }
+    let recent = ds.recent(2).unwrap();
+    let keys: Vec<&str> = recent.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, vec!["recent4", "recent3"]);
+    assert_eq!(recent[0].1 > recent[1].1, true);
+    // asking for more than there is returns everything
+    assert_eq!(ds.recent(10).unwrap().len(), 4);
+
+    teardown(vec!["fskv_test_recent"]);
+}
+
+#[test]
+fn test_search_values() {
+    let ds = Store::new("fskv_test_search_values", true).unwrap();
+    assert_eq!(ds.put("search1", "the quick brown fox").is_ok(), true);
+    assert_eq!(ds.put("search2", "jumps over").is_ok(), true);
+    assert_eq!(ds.put("search3", "the lazy dog").is_ok(), true);
+    let mut found = ds.search_values("the", None).unwrap();
+    found.sort();
+    assert_eq!(found, vec!["search1", "search3"]);
+    assert_eq!(ds.search_values("cat", None).unwrap().len(), 0);
+    // stop early
+    assert_eq!(ds.search_values("the", Some(1)).unwrap().len(), 1);
+
+    teardown(vec!["fskv_test_search_values"]);
+}
+
+#[test]
+fn test_key_partition() {
+    fn tenant(key: &str) -> (&str, &str) {
+        key.split_once(':').unwrap_or(("", key))
+    }
+    let ds = StoreBuilder::new("fskv_test_key_partition")
+        .create(true)
+        .key_partition(tenant)
+        .build()
+        .unwrap();
+    let keys = ["acme:alice:1", "acme:bob:2", "initech:alice:1", "no_tenant"];
+    for key in keys.iter() {
+        assert_eq!(ds.put(key, key).is_ok(), true);
+    }
+    for key in keys.iter() {
+        assert_eq!(ds.get(key).unwrap(), *key);
+    }
+    // same-tenant keys share the top-level directory
+    let count = |dir: &str| {
+        let mut count = 0;
+        let mut dirs = vec![Path::new("fskv_test_key_partition").join(dir)];
+        while let Some(d) = dirs.pop() {
+            for entry in fs::read_dir(d).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    count += 1;
+                }
+            }
+        }
+        count
+    };
+    assert_eq!(count("acme"), 2);
+    assert_eq!(count("initech"), 1);
+    // the partitioning is recorded, so it can't be forgotten on open
+    match Store::new("fskv_test_key_partition", false) {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    let reopened = StoreBuilder::new("fskv_test_key_partition")
+        .key_partition(tenant)
+        .build();
+    assert_eq!(reopened.unwrap().get("acme:bob:2").unwrap(), "acme:bob:2");
+
+    teardown(vec!["fskv_test_key_partition"]);
+}
+
+#[test]
+fn test_versions() {
+    let ds = StoreBuilder::new("fskv_test_versions")
+        .create(true)
+        .versioned(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("versioned", "1").is_ok(), true);
+    assert_eq!(ds.version_of("versioned").unwrap(), 1);
+    assert_eq!(ds.update("versioned", "2").is_ok(), true);
+    assert_eq!(ds.version_of("versioned").unwrap(), 2);
+    assert_eq!(ds.update_with_version("versioned", "3", 2).unwrap(), 3);
+    // a stale writer is rejected
+    match ds.update_with_version("versioned", "stale", 2) {
+        Err(FskvError::VersionMismatch { expected, actual }) => {
+            assert_eq!((expected, actual), (2, 3))
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("versioned").unwrap(), "3");
+    assert_eq!(ds.version_of("versioned").unwrap(), 3);
+    // versioning sticks to the store
+    let reopened = Store::new("fskv_test_versions", false).unwrap();
+    assert_eq!(reopened.update("versioned", "4").is_ok(), true);
+    assert_eq!(reopened.version_of("versioned").unwrap(), 4);
+    // and starts over once the key is gone
+    assert_eq!(ds.delete("versioned").is_ok(), true);
+    assert_eq!(ds.version_of("versioned").is_ok(), false);
+    assert_eq!(ds.update_with_version("versioned", "new", 0).unwrap(), 1);
+
+    teardown(vec!["fskv_test_versions"]);
+}
+
+#[test]
+fn test_get_lines() {
+    let ds = Store::new("fskv_test_get_lines", true).unwrap();
+    assert_eq!(ds.put("log", "first\nsecond\nthird\n").is_ok(), true);
+    let lines: Vec<String> = ds.get_lines("log").unwrap().map(|l| l.unwrap()).collect();
+    assert_eq!(lines, vec!["first", "second", "third"]);
+    match ds.get_lines("no_log") {
+        Err(FskvError::KeyNotFound(key)) => assert_eq!(key, "no_log"),
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("missing key was found"),
+    }
+
+    teardown(vec!["fskv_test_get_lines"]);
+}
+
+#[test]
+fn test_derive() {
+    let ds = Store::new("fskv_test_derive", true).unwrap();
+    assert_eq!(ds.put("derive_src", "shout").is_ok(), true);
+    assert_eq!(
+        ds.derive("derive_dst", "derive_src", |v| v.to_uppercase())
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("derive_dst").unwrap(), "SHOUT");
+    assert_eq!(ds.get("derive_src").unwrap(), "shout");
+    // deriving again replaces the destination
+    assert_eq!(ds.update("derive_src", "again").is_ok(), true);
+    assert_eq!(
+        ds.derive("derive_dst", "derive_src", |v| v.to_uppercase())
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("derive_dst").unwrap(), "AGAIN");
+    // a key can be derived from itself
+    assert_eq!(
+        ds.derive("derive_src", "derive_src", |v| format!("{}!", v))
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("derive_src").unwrap(), "again!");
+    // there's nothing to derive from a missing key
+    assert_eq!(
+        ds.derive("derive_dst", "missing", |v| v.to_string())
+            .is_ok(),
+        false
+    );
+
+    teardown(vec!["fskv_test_derive"]);
+}
+
+#[test]
+fn test_keys_between() {
+    let ds = Store::new("fskv_test_keys_between", true).unwrap();
+    assert_eq!(ds.put("early1", "foo").is_ok(), true);
+    assert_eq!(ds.put("early2", "foo").is_ok(), true);
+    thread::sleep(Duration::from_millis(20));
+    let middle = SystemTime::now();
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(ds.put("late1", "foo").is_ok(), true);
+    let end = SystemTime::now() + Duration::from_secs(1);
+
+    let mut late: Vec<String> = ds.keys_between(middle, end).map(|k| k.unwrap()).collect();
+    late.sort();
+    assert_eq!(late, vec!["late1"]);
+    let mut early: Vec<String> = ds
+        .keys_between(SystemTime::UNIX_EPOCH, middle)
+        .map(|k| k.unwrap())
+        .collect();
+    early.sort();
+    assert_eq!(early, vec!["early1", "early2"]);
+
+    teardown(vec!["fskv_test_keys_between"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_increment_field() {
+    let ds = Store::new("fskv_test_increment_field", true).unwrap();
+    assert_eq!(
+        ds.put("counters", r#"{"name": "hits", "count": 1}"#)
+            .is_ok(),
+        true
+    );
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..10 {
+                    ds.increment_field("counters", "count", 1).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(ds.increment_field("counters", "count", 0).unwrap(), 81);
+    // a missing field starts at 0
+    assert_eq!(ds.increment_field("counters", "misses", -2).unwrap(), -2);
+    // other fields are left alone
+    let value: serde_json::Value = serde_json::from_str(&ds.get("counters").unwrap()).unwrap();
+    assert_eq!(value["name"], "hits");
+    // only integer fields of objects can be incremented
+    match ds.increment_field("counters", "name", 1) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.put("not_an_object", "[1, 2]").is_ok(), true);
+    match ds.increment_field("not_an_object", "count", 1) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_increment_field"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_get_typed_or() {
+    let ds = Store::new("fskv_test_get_typed_or", true).unwrap();
+    assert_eq!(ds.put("ports", "[80, 443]").is_ok(), true);
+    assert_eq!(ds.put("corrupt", "[80, ").is_ok(), true);
+    assert_eq!(
+        ds.get_typed_or("ports", vec![8080u16]).unwrap(),
+        vec![80, 443]
+    );
+    assert_eq!(
+        ds.get_typed_or("no_ports", vec![8080u16]).unwrap(),
+        vec![8080]
+    );
+    match ds.get_typed_or("corrupt", vec![8080u16]) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_get_typed_or"]);
+}
+
+#[test]
+fn test_subscribe() {
+    let ds = Store::new("fskv_test_subscribe", true).unwrap();
+    let first = ds.subscribe();
+    let second = ds.clone().subscribe();
+    let dropped = ds.subscribe();
+    drop(dropped);
+
+    assert_eq!(ds.put("watched", "1").is_ok(), true);
+    assert_eq!(ds.update("watched", "2").is_ok(), true);
+    // failed writes aren't reported
+    assert_eq!(ds.put("watched", "3").is_ok(), false);
+    assert_eq!(ds.delete("watched").is_ok(), true);
+
+    let expected = vec![
+        MutationEvent {
+            key: "watched".to_string(),
+            kind: MutationKind::Put,
+        },
+        MutationEvent {
+            key: "watched".to_string(),
+            kind: MutationKind::Update,
+        },
+        MutationEvent {
+            key: "watched".to_string(),
+            kind: MutationKind::Delete,
+        },
+    ];
+    assert_eq!(first.try_iter().collect::<Vec<_>>(), expected);
+    assert_eq!(second.try_iter().collect::<Vec<_>>(), expected);
+
+    teardown(vec!["fskv_test_subscribe"]);
+}
+
+#[test]
+fn test_trim_trailing_newlines() {
+    let ds = StoreBuilder::new("fskv_test_trim_trailing_newlines")
+        .create(true)
+        .trim_trailing_newlines(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("newline", "value\n").is_ok(), true);
+    assert_eq!(ds.put("crlf", "value\r\n").is_ok(), true);
+    assert_eq!(ds.get("newline").unwrap(), "value");
+    assert_eq!(ds.get("crlf").unwrap(), "value");
+    assert_eq!(ds.get_bytes("newline").unwrap(), b"value\n");
+    // it's opt-in
+    let ds = Store::new("fskv_test_trim_trailing_newlines", false).unwrap();
+    assert_eq!(ds.get("newline").unwrap(), "value\n");
+
+    teardown(vec!["fskv_test_trim_trailing_newlines"]);
+}
+
+#[test]
+fn test_append_rotating() {
+    let ds = Store::new("fskv_test_append_rotating", true).unwrap();
+    for data in ["aaaaa", "bbbbb", "ccccc", "ddddd", "eeeee"].iter() {
+        assert_eq!(ds.append_rotating("rotating", data, 10).is_ok(), true);
+    }
+    assert_eq!(ds.get("rotating").unwrap(), "eeeee");
+    assert_eq!(ds.get("rotating.1").unwrap(), "cccccddddd");
+    assert_eq!(ds.get("rotating.2").unwrap(), "aaaaabbbbb");
+    assert_eq!(ds.get("rotating.3").is_ok(), false);
+    // plain appends just keep growing
+    assert_eq!(ds.append("growing", "a").is_ok(), true);
+    assert_eq!(ds.append("growing", "b").is_ok(), true);
+    assert_eq!(ds.get("growing").unwrap(), "ab");
+
+    teardown(vec!["fskv_test_append_rotating"]);
+}
+
+#[test]
+fn test_concat_prefix() {
+    let ds = Store::new("fskv_test_concat_prefix", true).unwrap();
+    // put out of order, they come back sorted by key
+    assert_eq!(ds.put("part:2", "c").is_ok(), true);
+    assert_eq!(ds.put("part:0", "a").is_ok(), true);
+    assert_eq!(ds.put("part:1", "b").is_ok(), true);
+    assert_eq!(ds.put("other:0", "x").is_ok(), true);
+    assert_eq!(ds.concat_prefix("part:", ",").unwrap(), "a,b,c");
+    assert_eq!(ds.concat_prefix("other:", ",").unwrap(), "x");
+    assert_eq!(ds.concat_prefix("none:", ",").unwrap(), "");
+
+    teardown(vec!["fskv_test_concat_prefix"]);
+}
+
+#[test]
+fn test_temp_dir() {
+    fs::create_dir_all("fskv_test_temp_dir_scratch").unwrap();
+    let ds = StoreBuilder::new("fskv_test_temp_dir")
+        .create(true)
+        .temp_dir("fskv_test_temp_dir_scratch")
+        .build()
+        .unwrap();
+    assert_eq!(ds.update("scratch", "first").is_ok(), true);
+    assert_eq!(ds.update("scratch", "second").is_ok(), true);
+    assert_eq!(ds.get("scratch").unwrap(), "second");
+    // nothing's left behind in the temporary directory
+    assert_eq!(
+        fs::read_dir("fskv_test_temp_dir_scratch").unwrap().count(),
+        0
+    );
+
+    // even when writing it fails
+    let backend = Arc::new(FaultyBackend::default());
+    let ds = StoreBuilder::new("fskv_test_temp_dir")
+        .temp_dir("fskv_test_temp_dir_scratch")
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    backend.fail_writes.store(true, Ordering::SeqCst);
+    assert_eq!(ds.update("scratch", "third").is_ok(), false);
+    assert_eq!(ds.get("scratch").unwrap(), "second");
+    assert_eq!(
+        fs::read_dir("fskv_test_temp_dir_scratch").unwrap().count(),
+        0
+    );
+
+    teardown(vec!["fskv_test_temp_dir", "fskv_test_temp_dir_scratch"]);
+}
+
+#[test]
+fn test_transition() {
+    let ds = Store::new("fskv_test_transition", true).unwrap();
+    assert_eq!(ds.put("state:pending", "job").is_ok(), true);
+    assert_eq!(ds.put("state:claimed", "job").is_ok(), true);
+    assert_eq!(
+        ds.transition(
+            &["state:pending", "state:claimed"],
+            &[("state:done", "job")]
+        )
+        .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("state:pending").is_ok(), false);
+    assert_eq!(ds.get("state:claimed").is_ok(), false);
+    assert_eq!(ds.get("state:done").unwrap(), "job");
+
+    // the destination already exists: nothing changes
+    assert_eq!(ds.put("state:a", "a").is_ok(), true);
+    assert_eq!(
+        ds.transition(&["state:a"], &[("state:b", "b"), ("state:done", "again")])
+            .is_ok(),
+        false
+    );
+    assert_eq!(ds.get("state:a").unwrap(), "a");
+    assert_eq!(ds.get("state:b").is_ok(), false);
+    assert_eq!(ds.get("state:done").unwrap(), "job");
+
+    // neither does it when there's nothing to delete
+    assert_eq!(
+        ds.transition(&["state:a", "state:missing"], &[("state:b", "b")])
+            .is_ok(),
+        false
+    );
+    assert_eq!(ds.get("state:a").unwrap(), "a");
+    assert_eq!(ds.get("state:b").is_ok(), false);
+
+    teardown(vec!["fskv_test_transition"]);
+}
+
+#[test]
+fn test_open_value_file() {
+    let ds = Store::new("fskv_test_open_value_file", true).unwrap();
+    assert_eq!(ds.put("file", "some value").is_ok(), true);
+    let mut file = ds.open_value_file("file").unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 10);
+    let mut value = String::new();
+    assert_eq!(file.read_to_string(&mut value).is_ok(), true);
+    assert_eq!(value, "some value");
+    // an update replaces the file, the open handle still sees the old one
+    assert_eq!(ds.update("file", "new").is_ok(), true);
+    let mut old = String::new();
+    assert_eq!(file.seek(SeekFrom::Start(0)).is_ok(), true);
+    assert_eq!(file.read_to_string(&mut old).is_ok(), true);
+    assert_eq!(old, "some value");
+    assert_eq!(ds.open_value_file("missing").is_ok(), false);
+
+    teardown(vec!["fskv_test_open_value_file"]);
+}
+
+// the directory holding some key file in a store
+fn some_bucket(root: &str) -> PathBuf {
+    let mut dirs = vec![PathBuf::from(root)];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if !path.file_name().unwrap().to_string_lossy().starts_with('.') {
+                return dir;
+            }
+        }
+    }
+    panic!("no keys in {}", root);
+}
+
+#[test]
+fn test_validate_on_open() {
+    let ds = Store::new("fskv_test_validate_on_open", true).unwrap();
+    assert_eq!(ds.put("valid", "foo").is_ok(), true);
+    let validated = || {
+        StoreBuilder::new("fskv_test_validate_on_open")
+            .validate_on_open(true)
+            .build()
+    };
+    assert_eq!(validated().is_ok(), true);
+
+    // an update that never finished
+    let stray = some_bucket("fskv_test_validate_on_open").join(".1234.tmp");
+    fs::write(&stray, "half").unwrap();
+    match validated() {
+        Err(FskvError::CorruptStore(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(
+        Store::new("fskv_test_validate_on_open", false).is_ok(),
+        true
+    );
+    fs::remove_file(&stray).unwrap();
+
+    // a file where a directory should be
+    fs::write("fskv_test_validate_on_open/stray", "foo").unwrap();
+    match validated() {
+        Err(FskvError::CorruptStore(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_validate_on_open"]);
+}
+
+#[test]
+fn test_read_into() {
+    let ds = Store::new("fskv_test_read_into", true).unwrap();
+    assert_eq!(ds.put("long", "a much longer value").is_ok(), true);
+    assert_eq!(ds.put("short", "foo").is_ok(), true);
+
+    let mut buf = Vec::new();
+    assert_eq!(ds.read_into("long", &mut buf).unwrap(), 19);
+    assert_eq!(buf, b"a much longer value");
+    let capacity = buf.capacity();
+    assert_eq!(ds.read_into("short", &mut buf).unwrap(), 3);
+    assert_eq!(buf, b"foo");
+    assert_eq!(buf.capacity(), capacity);
+    match ds.read_into("missing", &mut buf) {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_read_into"]);
+}
+
+// the file holding `key` in a store, wherever it is
+fn key_file(root: &str, key: &str) -> PathBuf {
+    let mut dirs = vec![PathBuf::from(root)];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.file_name().unwrap() == key {
+                return path;
+            }
+        }
+    }
+    panic!("no {} in {}", key, root);
+}
+
+#[test]
+fn test_repair_placement() {
+    let ds = StoreBuilder::new("fskv_test_repair_placement")
+        .create(true)
+        .versioned(true)
+        .creation_times(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("misplaced", "foo").is_ok(), true);
+    assert_eq!(ds.update("misplaced", "bar").is_ok(), true);
+    assert_eq!(ds.put("in_place", "baz").is_ok(), true);
+    assert_eq!(ds.repair_placement().unwrap(), 0);
+    let created = ds.created_at("misplaced").unwrap();
+
+    // move the key, and its sidecars, to a bucket it doesn't hash to
+    let file = key_file("fskv_test_repair_placement", "misplaced");
+    let wrong_bucket = Path::new("fskv_test_repair_placement/00/00/00");
+    fs::create_dir_all(wrong_bucket).unwrap();
+    fs::rename(&file, wrong_bucket.join("misplaced")).unwrap();
+    for sidecar in [".misplaced.version", ".misplaced.created"] {
+        fs::rename(file.with_file_name(sidecar), wrong_bucket.join(sidecar)).unwrap();
+    }
+    match ds.get("misplaced") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    assert_eq!(ds.repair_placement().unwrap(), 1);
+    assert_eq!(ds.get("misplaced").unwrap(), "bar");
+    assert_eq!(ds.version_of("misplaced").unwrap(), 2);
+    assert_eq!(ds.get("in_place").unwrap(), "baz");
+    assert_eq!(ds.created_at("misplaced").unwrap(), created);
+    assert_eq!(wrong_bucket.join("misplaced").exists(), false);
+    assert_eq!(ds.find_orphans(false).unwrap(), Vec::<PathBuf>::new());
+
+    teardown(vec!["fskv_test_repair_placement"]);
+}
+
+#[test]
+fn test_promote() {
+    let live = Store::new("fskv_test_promote", true).unwrap();
+    assert_eq!(live.put("dataset", "old").is_ok(), true);
+    let staged = Store::new("fskv_test_promote_staging", true).unwrap();
+    assert_eq!(staged.put("dataset", "new").is_ok(), true);
+
+    let backup = promote("fskv_test_promote_staging", "fskv_test_promote")
+        .unwrap()
+        .unwrap();
+    let live = Store::new("fskv_test_promote", false).unwrap();
+    assert_eq!(live.get("dataset").unwrap(), "new");
+    let old = Store::new(backup.to_str().unwrap(), false).unwrap();
+    assert_eq!(old.get("dataset").unwrap(), "old");
+    assert_eq!(Path::new("fskv_test_promote_staging").exists(), false);
+
+    // nothing to promote: the live store stays put
+    assert_eq!(
+        promote("fskv_test_promote_staging", "fskv_test_promote").is_err(),
+        true
+    );
+    assert_eq!(live.get("dataset").unwrap(), "new");
+
+    teardown(vec!["fskv_test_promote", backup.to_str().unwrap()]);
+}
+
+#[test]
+fn test_shard_counts() {
+    let ds = Store::new("fskv_test_shard_counts", true).unwrap();
+    for i in 0..50 {
+        assert_eq!(ds.put(&format!("key{}", i), "foo").is_ok(), true);
+    }
+
+    let counts = ds.shard_counts().unwrap();
+    assert_eq!(counts.values().sum::<usize>(), 50);
+    for (shard, count) in counts {
+        assert_eq!(shard.len(), 4);
+        assert_eq!(count > 0, true);
+    }
+
+    teardown(vec!["fskv_test_shard_counts"]);
+}
+
+#[test]
+fn test_get_with_checksum() {
+    let ds = Store::new("fskv_test_get_with_checksum", true).unwrap();
+    let value = "The quick brown fox jumps over the lazy dog";
+    assert_eq!(ds.put("fox", value).is_ok(), true);
+
+    // the well-known CRC32 of that sentence
+    assert_eq!(
+        ds.get_with_checksum("fox").unwrap(),
+        (value.to_string(), 0x414fa339)
+    );
+    match ds.get_with_checksum("missing") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // the checksum stored when it was written, which a value corrupted
+    // since no longer matches
+    ds.put_reader_checked("checked", value.as_bytes()).unwrap();
+    assert_eq!(
+        ds.get_with_checksum("checked").unwrap(),
+        (value.to_string(), 0x414fa339)
+    );
+    let file = key_file("fskv_test_get_with_checksum", "checked");
+    let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+    let mut f = fs::OpenOptions::new().write(true).open(&file).unwrap();
+    f.write_all(b"t").unwrap();
+    f.set_modified(mtime).unwrap();
+    drop(f);
+    let (read, checksum) = ds.get_with_checksum("checked").unwrap();
+    assert_eq!(read, value.replacen('T', "t", 1));
+    assert_eq!(checksum, 0x414fa339);
+    assert_ne!(checksum, crc32fast::hash(read.as_bytes()));
+
+    teardown(vec!["fskv_test_get_with_checksum"]);
+}
+
+#[test]
+fn test_max_keys() {
+    let ds = Store::new("fskv_test_max_keys", true).unwrap();
+    assert_eq!(ds.put("existing", "foo").is_ok(), true);
+
+    // keys already there count towards the limit
+    let ds = StoreBuilder::new("fskv_test_max_keys")
+        .max_keys(3)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("a", "foo").is_ok(), true);
+    assert_eq!(ds.update("b", "foo").is_ok(), true);
+    match ds.put("c", "foo") {
+        Err(FskvError::Full) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match ds.update("c", "foo") {
+        Err(FskvError::Full) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("c").is_err(), true);
+    // an existing key is reported as such, even with no room left, and a
+    // failed put doesn't take up room
+    match ds.put("a", "bar") {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "a"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match ds.rename_key("existing", "a") {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "a"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // existing keys can still be written
+    assert_eq!(ds.update("a", "bar").is_ok(), true);
+    assert_eq!(ds.get("a").unwrap(), "bar");
+
+    // and deleting makes room again
+    assert_eq!(ds.delete("b").is_ok(), true);
+    assert_eq!(ds.put("c", "foo").is_ok(), true);
+    assert_eq!(ds.put("d", "foo").is_err(), true);
+
+    teardown(vec!["fskv_test_max_keys"]);
+}
+
+#[test]
+fn test_rename_key() {
+    let ds = StoreBuilder::new("fskv_test_rename_key")
+        .create(true)
+        .versioned(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("from", "foo").is_ok(), true);
+    assert_eq!(ds.put("taken", "bar").is_ok(), true);
+
+    match ds.rename_key("from", "taken") {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "taken"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("from").unwrap(), "foo");
+    assert_eq!(ds.get("taken").unwrap(), "bar");
+
+    assert_eq!(ds.rename_key("from", "to").is_ok(), true);
+    assert_eq!(ds.get("to").unwrap(), "foo");
+    assert_eq!(ds.version_of("to").unwrap(), 1);
+    match ds.get("from") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    assert_eq!(ds.rename_key_force("to", "taken").is_ok(), true);
+    assert_eq!(ds.get("taken").unwrap(), "foo");
+    assert_eq!(ds.version_of("taken").unwrap(), 2);
+    assert_eq!(ds.get("to").is_err(), true);
+    match ds.rename_key_force("to", "taken") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("taken").unwrap(), "foo");
+
+    // renaming a key to itself leaves it as it is
+    let len = ds.approx_len().unwrap();
+    match ds.rename_key("taken", "taken") {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "taken"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match ds.rename_map("taken", "taken", |v| v.to_uppercase()) {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "taken"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.rename_key_force("taken", "taken").is_ok(), true);
+    assert_eq!(ds.get("taken").unwrap(), "foo");
+    assert_eq!(ds.version_of("taken").unwrap(), 2);
+    assert_eq!(ds.approx_len().unwrap(), len);
+    match ds.rename_key_force("to", "to") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_rename_key"]);
+}
+
+#[test]
+fn test_export_with_progress() {
+    let ds = Store::new("fskv_test_export_with_progress", true).unwrap();
+    for i in 0..5 {
+        assert_eq!(ds.put(&format!("key{}", i), "foo").is_ok(), true);
+    }
+
+    let mut calls = Vec::new();
+    let mut buffer = Vec::new();
+    assert_eq!(
+        ds.export_with_progress(&mut buffer, |done, total| calls.push((done, total)))
+            .is_ok(),
+        true
+    );
+    assert_eq!(calls, (0..=5).map(|done| (done, 5)).collect::<Vec<_>>());
+    // the archive is the same as a plain export
+    assert_eq!(tar::Archive::new(&buffer[..]).entries().unwrap().count(), 5);
+
+    teardown(vec!["fskv_test_export_with_progress"]);
+}
+
+#[test]
+fn test_is_text() {
+    let ds = Store::new("fskv_test_is_text", true).unwrap();
+    // long enough for characters to straddle the chunks it's read in
+    let text = "façade ".repeat(5000);
+    assert_eq!(ds.put("text", &text).is_ok(), true);
+    assert_eq!(ds.put("empty", "").is_ok(), true);
+    assert_eq!(ds.put("binary", "placeholder").is_ok(), true);
+    let binary = key_file("fskv_test_is_text", "binary");
+    fs::write(&binary, [0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap();
+    assert_eq!(ds.put("truncated", "placeholder").is_ok(), true);
+    let truncated = key_file("fskv_test_is_text", "truncated");
+    fs::write(&truncated, &"ç".as_bytes()[..1]).unwrap();
+
+    assert_eq!(ds.is_text("text").unwrap(), true);
+    assert_eq!(ds.is_text("empty").unwrap(), true);
+    assert_eq!(ds.is_text("binary").unwrap(), false);
+    assert_eq!(ds.is_text("truncated").unwrap(), false);
+    assert_eq!(ds.is_text("missing").is_err(), true);
+
+    teardown(vec!["fskv_test_is_text"]);
+}
+
+#[test]
+fn test_take_once() {
+    let ds = Store::new("fskv_test_take_once", true).unwrap();
+    for round in 0..10 {
+        let key = format!("job{}", round);
+        assert_eq!(ds.put(&key, "work").is_ok(), true);
+
+        let consumers: Vec<_> = (0..8)
+            .map(|_| {
+                let (ds, key) = (ds.clone(), key.clone());
+                thread::spawn(move || ds.take_once(&key).unwrap())
+            })
+            .collect();
+        let taken: Vec<String> = consumers
+            .into_iter()
+            .filter_map(|c| c.join().unwrap())
+            .collect();
+        assert_eq!(taken, vec!["work".to_string()]);
+        assert_eq!(ds.get(&key).is_err(), true);
+    }
+    assert_eq!(ds.take_once("missing").unwrap(), None);
+
+    teardown(vec!["fskv_test_take_once"]);
+}
+
+#[test]
+fn test_root_path() {
+    let ds = Store::new("./fskv_test_root_path/../fskv_test_root_path", true).unwrap();
+    let expected = std::env::current_dir().unwrap().join("fskv_test_root_path");
+    assert_eq!(ds.root_path(), fs::canonicalize(expected).unwrap());
+    assert_eq!(ds.root_path().is_absolute(), true);
+
+    teardown(vec!["fskv_test_root_path"]);
+}
+
+#[test]
+fn test_put_idempotent() {
+    let ds = Store::new("fskv_test_put_idempotent", true).unwrap();
+    for _ in 0..3 {
+        assert_eq!(ds.put_idempotent("setting", "on").is_ok(), true);
+    }
+    assert_eq!(ds.get("setting").unwrap(), "on");
+
+    match ds.put_idempotent("setting", "off") {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "setting"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // a plain put doesn't accept the same value
+    match ds.put("setting", "on") {
+        Err(FskvError::KeyExists(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("setting").unwrap(), "on");
+
+    // retrying still succeeds once the store is full
+    let ds = StoreBuilder::new("fskv_test_put_idempotent")
+        .max_keys(1)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put_idempotent("setting", "on").is_ok(), true);
+    match ds.put_idempotent("other", "on") {
+        Err(FskvError::Full) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_put_idempotent"]);
+}
+
+#[test]
+fn test_reset_counter() {
+    let ds = Store::new("fskv_test_reset_counter", true).unwrap();
+    assert_eq!(ds.increment("hits", 5).unwrap(), 5);
+    assert_eq!(ds.increment("hits", -2).unwrap(), 3);
+    assert_eq!(ds.reset_counter("hits", 0).is_ok(), true);
+    assert_eq!(ds.increment("hits", 1).unwrap(), 1);
+
+    // resets racing with increments: the counter always holds an integer,
+    // and once everything's done it's the last reset plus what came after
+    let incrementers: Vec<_> = (0..4)
+        .map(|_| {
+            let ds = ds.clone();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    ds.increment("hits", 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for _ in 0..10 {
+        assert_eq!(ds.reset_counter("hits", 1000).is_ok(), true);
+        assert_eq!(ds.get("hits").unwrap().parse::<i64>().is_ok(), true);
+    }
+    for i in incrementers {
+        i.join().unwrap();
+    }
+    let value: i64 = ds.get("hits").unwrap().parse().unwrap();
+    assert_eq!((1000..=1100).contains(&value), true);
+
+    assert_eq!(ds.reset_counter("hits", 42).is_ok(), true);
+    assert_eq!(ds.increment("hits", 1).unwrap(), 43);
+
+    // only integers can be incremented
+    assert_eq!(ds.put("name", "foo").is_ok(), true);
+    match ds.increment("name", 1) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_reset_counter"]);
+}
+
+#[test]
+fn test_namespaces() {
+    let ds = Store::new("fskv_test_namespaces", true).unwrap();
+    assert_eq!(ds.put("key", "foo").is_ok(), true);
+    for tenant in ["tenant_b", "tenant_a"] {
+        let ns = Store::new(&format!("fskv_test_namespaces/{}", tenant), true).unwrap();
+        assert_eq!(ns.put("key", tenant).is_ok(), true);
+    }
+    fs::create_dir("fskv_test_namespaces/not_a_store").unwrap();
+
+    assert_eq!(ds.namespaces().unwrap(), vec!["tenant_a", "tenant_b"]);
+    // they're separate stores
+    assert_eq!(ds.get("key").unwrap(), "foo");
+
+    teardown(vec!["fskv_test_namespaces"]);
+}
+
+#[test]
+fn test_value_extension() {
+    let ds = StoreBuilder::new("fskv_test_value_extension")
+        .create(true)
+        .value_extension(".json")
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("config", "{}").is_ok(), true);
+    assert_eq!(ds.update("users", "[]").is_ok(), true);
+    assert_eq!(ds.get("config").unwrap(), "{}");
+
+    let file = key_file("fskv_test_value_extension", "config.json");
+    assert_eq!(fs::read_to_string(file).unwrap(), "{}");
+    let mut keys = ds.keys().collect::<Result<Vec<_>, _>>().unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["config", "users"]);
+
+    // the extension sticks to the store
+    let ds = Store::new("fskv_test_value_extension", false).unwrap();
+    assert_eq!(ds.get("users").unwrap(), "[]");
+    assert_eq!(ds.delete("users").is_ok(), true);
+    match StoreBuilder::new("fskv_test_value_extension")
+        .value_extension(".txt")
+        .build()
+    {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_value_extension"]);
+}
+
+#[test]
+fn test_patch() {
+    let ds = Store::new("fskv_test_patch", true).unwrap();
+    let old = "line\n".repeat(1000);
+    let new = old.replace("line\nline\nline", "line\nchanged\nline") + "tail\n";
+    assert_eq!(ds.put("doc", &old).is_ok(), true);
+
+    let mut delta = Vec::new();
+    bsdiff::diff(old.as_bytes(), new.as_bytes(), &mut delta).unwrap();
+    assert_eq!(ds.patch("doc", &delta).is_ok(), true);
+    assert_eq!(ds.get("doc").unwrap(), new);
+
+    // a delta that's not a delta
+    match ds.patch("doc", b"garbage") {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("doc").unwrap(), new);
+    match ds.patch("missing", &delta) {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_patch"]);
+}
+
+#[test]
+fn test_append_bounded() {
+    let ds = Store::new("fskv_test_append_bounded", true).unwrap();
+    for i in 0..5 {
+        let line = format!("line{}\n", i);
+        assert_eq!(
+            ds.append_bounded("log", &line, 10, KeepEnd::Tail).is_ok(),
+            true
+        );
+    }
+    // only the most recent bytes are left
+    assert_eq!(ds.get("log").unwrap(), "ne3\nline4\n");
+
+    for i in 0..5 {
+        let line = format!("line{}\n", i);
+        assert_eq!(
+            ds.append_bounded("first", &line, 10, KeepEnd::Head).is_ok(),
+            true
+        );
+    }
+    assert_eq!(ds.get("first").unwrap(), "line0\nline");
+
+    // characters aren't split
+    assert_eq!(
+        ds.append_bounded("text", "aéé", 4, KeepEnd::Tail).is_ok(),
+        true
+    );
+    assert_eq!(ds.get("text").unwrap(), "éé");
+    assert_eq!(
+        ds.append_bounded("text", "a", 4, KeepEnd::Tail).is_ok(),
+        true
+    );
+    assert_eq!(ds.get("text").unwrap(), "éa");
+
+    teardown(vec!["fskv_test_append_bounded"]);
+}
+
+#[test]
+fn test_export_where() {
+    let ds = Store::new("fskv_test_export_where", true).unwrap();
+    let entries = [
+        ("critical:db", "1"),
+        ("critical:auth", "2"),
+        ("cache:page", "3"),
+    ];
+    for (k, v) in entries.iter() {
+        assert_eq!(ds.put(k, v).is_ok(), true);
+    }
+
+    let mut buffer = Vec::new();
+    let exported = ds
+        .export_where(&mut buffer, |key| key.starts_with("critical:"))
+        .unwrap();
+    assert_eq!(exported, 2);
+
+    let fresh = Store::new("fskv_test_export_where_import", true).unwrap();
+    assert_eq!(fresh.import_tar(&buffer[..]).is_ok(), true);
+    let mut keys = fresh.keys().collect::<Result<Vec<_>, _>>().unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["critical:auth", "critical:db"]);
+    assert_eq!(fresh.get("critical:db").unwrap(), "1");
+
+    teardown(vec![
+        "fskv_test_export_where",
+        "fskv_test_export_where_import",
+    ]);
+}
+
+#[test]
+fn test_fingerprint() {
+    let a = Store::new("fskv_test_fingerprint_a", true).unwrap();
+    let b = Store::new("fskv_test_fingerprint_b", true).unwrap();
+    let entries = [("one", "1"), ("two", "2"), ("three", "3")];
+    for (k, v) in entries.iter() {
+        assert_eq!(a.put(k, v).is_ok(), true);
+    }
+    // same data, written in a different order
+    for (k, v) in entries.iter().rev() {
+        assert_eq!(b.put(k, v).is_ok(), true);
+    }
+    assert_eq!(a.fingerprint().unwrap(), b.fingerprint().unwrap());
+
+    assert_eq!(b.update("two", "22").is_ok(), true);
+    assert_eq!(a.fingerprint().unwrap() != b.fingerprint().unwrap(), true);
+
+    teardown(vec!["fskv_test_fingerprint_a", "fskv_test_fingerprint_b"]);
+}
+
+#[test]
+fn test_strict_not_found() {
+    let ds = StoreBuilder::new("fskv_test_strict_not_found")
+        .create(true)
+        .strict_not_found(true)
+        .build()
+        .unwrap();
+    // deleting a key leaves its directory behind
+    assert_eq!(ds.put("deleted", "foo").is_ok(), true);
+    assert_eq!(ds.delete("deleted").is_ok(), true);
+
+    match ds.get("deleted") {
+        Err(FskvError::KeyNotFound(key)) => assert_eq!(key, "deleted"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match ds.get("never_written") {
+        Err(FskvError::BucketNotFound(key)) => assert_eq!(key, "never_written"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // which are both misses
+    assert_eq!(ds.increment("new_counter", 1).unwrap(), 1);
+
+    // not by default
+    let ds = Store::new("fskv_test_strict_not_found", false).unwrap();
+    match ds.get("never_written") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_strict_not_found"]);
+}
+
+#[test]
+fn test_new_with_seed() {
+    let seed = [("admin", "root"), ("theme", "dark")];
+    let ds = Store::new_with_seed("fskv_test_new_with_seed", &seed).unwrap();
+    assert_eq!(ds.get("admin").unwrap(), "root");
+    assert_eq!(ds.get("theme").unwrap(), "dark");
+
+    // reopening doesn't put the seed back, nor fail because it's there
+    assert_eq!(ds.update("theme", "light").is_ok(), true);
+    assert_eq!(ds.delete("admin").is_ok(), true);
+    let ds = Store::new_with_seed("fskv_test_new_with_seed", &seed).unwrap();
+    assert_eq!(ds.get("theme").unwrap(), "light");
+    assert_eq!(ds.get("admin").is_err(), true);
+
+    // a seed that can't be put leaves no store behind, so it's tried again
+    let bad_seed = [("admin", "root"), ("", "no key")];
+    assert_eq!(
+        Store::new_with_seed("fskv_test_new_with_seed_bad", &bad_seed).is_err(),
+        true
+    );
+    assert_eq!(Path::new("fskv_test_new_with_seed_bad").exists(), false);
+    fs::create_dir("fskv_test_new_with_seed_bad").unwrap();
+    let ds = Store::new_with_seed("fskv_test_new_with_seed_bad", &seed).unwrap();
+    assert_eq!(ds.get("admin").unwrap(), "root");
+    let leftovers = fs::read_dir(".")
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy()
+                .starts_with("fskv_test_new_with_seed_bad.")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    teardown(vec![
+        "fskv_test_new_with_seed",
+        "fskv_test_new_with_seed_bad",
+    ]);
+}
+
+#[test]
+fn test_put_reader_checked() {
+    let ds = Store::new("fskv_test_put_reader_checked", true).unwrap();
+    let value = "0123456789abcdef".repeat(10000);
+    let checksum = ds.put_reader_checked("big", value.as_bytes()).unwrap();
+    assert_eq!(
+        checksum,
+        format!("{:08x}", crc32fast::hash(value.as_bytes()))
+    );
+    assert_eq!(ds.get("big").unwrap(), value);
+    match ds.put_reader_checked("big", &b"other"[..]) {
+        Err(FskvError::KeyExists(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let mut read = String::new();
+    let mut reader = ds.get_reader_checked("big").unwrap();
+    assert_eq!(reader.read_to_string(&mut read).is_ok(), true);
+    assert_eq!(read, value);
+
+    // flip a byte in place, leaving its size and mtime alone
+    let file = key_file("fskv_test_put_reader_checked", "big");
+    let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+    let mut f = fs::OpenOptions::new().write(true).open(&file).unwrap();
+    f.seek(SeekFrom::Start(5000)).unwrap();
+    f.write_all(b"X").unwrap();
+    f.set_modified(mtime).unwrap();
+    drop(f);
+    let mut reader = ds.get_reader_checked("big").unwrap();
+    assert_eq!(reader.read_to_end(&mut Vec::new()).is_err(), true);
+
+    // a value written otherwise has no checksum
+    assert_eq!(ds.update("big", "new").is_ok(), true);
+    match ds.get_reader_checked("big") {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+
+    // checksums go away with the key, however it's removed
+    assert_eq!(ds.delete("big").is_ok(), true);
+    ds.put_reader_checked("taken", &b"queued"[..]).unwrap();
+    assert_eq!(ds.take_once("taken").unwrap(), Some("queued".to_string()));
+    assert_eq!(ds.find_orphans(false).unwrap(), Vec::<PathBuf>::new());
+    ds.put_reader_checked("big", &b"again"[..]).unwrap();
+    let mut read = String::new();
+    let mut reader = ds.get_reader_checked("big").unwrap();
+    assert_eq!(reader.read_to_string(&mut read).is_ok(), true);
+    assert_eq!(read, "again");
+
+    teardown(vec!["fskv_test_put_reader_checked"]);
+}
+
+#[test]
+fn test_get_many_parallel() {
+    let ds = Store::new("fskv_test_get_many_parallel", true).unwrap();
+    let keys: Vec<String> = (0..100).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter().step_by(2) {
+        assert_eq!(ds.put(key, &key.to_uppercase()).is_ok(), true);
+    }
+
+    let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+    let sequential: Vec<_> = keys.iter().map(|k| ds.get(k).ok()).collect();
+    for workers in [0, 1, 4, 200] {
+        let parallel: Vec<_> = ds
+            .get_many_parallel(&keys, workers)
+            .into_iter()
+            .map(|r| r.ok())
+            .collect();
+        assert_eq!(parallel, sequential);
+    }
+    assert_eq!(ds.get_many_parallel(&[], 4).is_empty(), true);
+
+    teardown(vec!["fskv_test_get_many_parallel"]);
+}
+
+#[test]
+fn test_key_salt() {
+    let plain = Store::new("fskv_test_key_salt_plain", true).unwrap();
+    let salted = StoreBuilder::new("fskv_test_key_salt")
+        .create(true)
+        .key_salt("pepper")
+        .build()
+        .unwrap();
+    assert_eq!(plain.put("user:1", "foo").is_ok(), true);
+    assert_eq!(salted.put("user:1", "foo").is_ok(), true);
+    assert_eq!(plain.get("user:1").unwrap(), "foo");
+    assert_eq!(salted.get("user:1").unwrap(), "foo");
+
+    let relative = |root: &str| {
+        key_file(root, "user:1")
+            .strip_prefix(root)
+            .unwrap()
+            .to_path_buf()
+    };
+    assert_eq!(
+        relative("fskv_test_key_salt") != relative("fskv_test_key_salt_plain"),
+        true
+    );
+
+    // the salt sticks to the store
+    let reopened = Store::new("fskv_test_key_salt", false).unwrap();
+    assert_eq!(reopened.get("user:1").unwrap(), "foo");
+    match StoreBuilder::new("fskv_test_key_salt")
+        .key_salt("salt")
+        .build()
+    {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_key_salt", "fskv_test_key_salt_plain"]);
+}
+
+#[test]
+fn test_reconcile() {
+    let ds = Store::new("fskv_test_reconcile", true).unwrap();
+    assert_eq!(ds.put("copied", "new").is_ok(), true);
+    assert_eq!(ds.put("moved", "foo").is_ok(), true);
+    assert_eq!(ds.put("fine", "bar").is_ok(), true);
+    assert_eq!(ds.reconcile().unwrap(), ReconcileReport::default());
+
+    // an old copy left behind, and a key that's only in the wrong place
+    let old_bucket = Path::new("fskv_test_reconcile/00/00/00");
+    fs::create_dir_all(old_bucket).unwrap();
+    fs::write(old_bucket.join("copied"), "old").unwrap();
+    fs::rename(
+        key_file("fskv_test_reconcile", "moved"),
+        old_bucket.join("moved"),
+    )
+    .unwrap();
+
+    let root = ds.root_path().join("00/00/00");
+    let report = ds.reconcile().unwrap();
+    assert_eq!(
+        report.duplicates,
+        vec![("copied".to_string(), root.join("copied"))]
+    );
+    assert_eq!(
+        report.misplaced,
+        vec![("moved".to_string(), root.join("moved"))]
+    );
+    // nothing was touched
+    assert_eq!(ds.get("copied").unwrap(), "new");
+    assert_eq!(ds.get("moved").is_err(), true);
+
+    teardown(vec!["fskv_test_reconcile"]);
+}
+
+#[test]
+fn test_coalesce_updates() {
+    let ds = StoreBuilder::new("fskv_test_coalesce_updates")
+        .create(true)
+        .coalesce_updates(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("hot", "0").is_ok(), true);
+    // every write to disk is an event
+    let events = ds.subscribe();
+
+    for i in 1..=50 {
+        assert_eq!(ds.update("hot", &i.to_string()).is_ok(), true);
+    }
+    assert_eq!(events.try_recv().is_err(), true);
+    assert_eq!(ds.get("hot").unwrap(), "0");
+
+    assert_eq!(ds.flush().is_ok(), true);
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![MutationEvent {
+            key: "hot".to_string(),
+            kind: MutationKind::Update
+        }]
+    );
+    assert_eq!(ds.get("hot").unwrap(), "50");
+
+    // other writes don't get overwritten by an update held back earlier
+    assert_eq!(ds.update("hot", "51").is_ok(), true);
+    assert_eq!(ds.delete("hot").is_ok(), true);
+    assert_eq!(ds.flush().is_ok(), true);
+    assert_eq!(ds.get("hot").is_err(), true);
+
+    // once the window is over, the next update writes them out
+    let ds = StoreBuilder::new("fskv_test_coalesce_updates")
+        .coalesce_updates(Duration::from_millis(20))
+        .build()
+        .unwrap();
+    assert_eq!(ds.update("warm", "1").is_ok(), true);
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(ds.update("warm", "2").is_ok(), true);
+    assert_eq!(ds.get("warm").unwrap(), "2");
+
+    teardown(vec!["fskv_test_coalesce_updates"]);
+}
+
+#[test]
+fn test_clone_into() {
+    let ds = Store::new("fskv_test_clone_into", true).unwrap();
+    let entries = [("one", "1"), ("two", "2"), ("empty", "")];
+    for (k, v) in entries.iter() {
+        assert_eq!(ds.put(k, v).is_ok(), true);
+    }
+
+    // a salt can't be added to an existing store
+    let clone = ds
+        .clone_into(StoreBuilder::new("fskv_test_clone_into_salted").key_salt("pepper"))
+        .unwrap();
+    for (k, v) in entries.iter() {
+        assert_eq!(clone.get(k).unwrap(), *v);
+        assert_eq!(ds.get(k).unwrap(), *v);
+    }
+    let reopened = Store::new("fskv_test_clone_into_salted", false).unwrap();
+    assert_eq!(reopened.get("two").unwrap(), "2");
+
+    teardown(vec!["fskv_test_clone_into", "fskv_test_clone_into_salted"]);
+}
+
+#[test]
+fn test_put_if() {
+    let ds = Store::new("fskv_test_put_if", true).unwrap();
+    // only ever move forward
+    let put_if_higher = |value: &str| {
+        let new: i64 = value.parse().unwrap();
+        ds.put_if("high_score", value, |current| {
+            current.is_none_or(|current| current.parse::<i64>().unwrap() < new)
+        })
+        .unwrap()
+    };
+
+    assert_eq!(put_if_higher("10"), true);
+    assert_eq!(put_if_higher("5"), false);
+    assert_eq!(ds.get("high_score").unwrap(), "10");
+    assert_eq!(put_if_higher("10"), false);
+    assert_eq!(put_if_higher("42"), true);
+    assert_eq!(ds.get("high_score").unwrap(), "42");
+
+    teardown(vec!["fskv_test_put_if"]);
+}
+
+#[test]
+fn test_snapshot_keys() {
+    let ds = StoreBuilder::new("fskv_test_snapshot_keys")
+        .create(true)
+        .snapshots(true)
+        .build()
+        .unwrap();
+    // each writer adds its keys in order, so any point-in-time list of
+    // them has no gaps
+    let writers: Vec<_> = (0..4)
+        .map(|w| {
+            let ds = ds.clone();
+            thread::spawn(move || {
+                for n in 0..100 {
+                    ds.put(&format!("w{}:{:03}", w, n), "foo").unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for _ in 0..20 {
+        let keys = ds.snapshot_keys().unwrap();
+        for w in 0..4 {
+            let prefix = format!("w{}:", w);
+            let seen: Vec<&String> = keys.iter().filter(|k| k.starts_with(&prefix)).collect();
+            for (n, key) in seen.iter().enumerate() {
+                assert_eq!(**key, format!("{}{:03}", prefix, n));
+            }
+        }
+    }
+    for w in writers {
+        w.join().unwrap();
+    }
+    assert_eq!(ds.snapshot_keys().unwrap().len(), 400);
+
+    // it has to be turned on when the store is created
+    let plain = Store::new("fskv_test_snapshot_keys_plain", true).unwrap();
+    match plain.snapshot_keys() {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match StoreBuilder::new("fskv_test_snapshot_keys_plain")
+        .snapshots(true)
+        .build()
+    {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec![
+        "fskv_test_snapshot_keys",
+        "fskv_test_snapshot_keys_plain",
+    ]);
+}
+
+#[test]
+fn test_dedup_stats() {
+    let ds = Store::new("fskv_test_dedup_stats", true).unwrap();
+    assert_eq!(ds.dedup_stats().unwrap(), DedupStats::default());
+    let entries = [
+        ("a", "shared value"),
+        ("b", "shared value"),
+        ("c", "shared value"),
+        ("d", "other"),
+        ("e", "other"),
+        ("f", "unique"),
+    ];
+    for (k, v) in entries.iter() {
+        assert_eq!(ds.put(k, v).is_ok(), true);
+    }
+
+    assert_eq!(
+        ds.dedup_stats().unwrap(),
+        DedupStats {
+            total_values: 6,
+            unique_values: 3,
+            saveable_bytes: 2 * 12 + 5,
+        }
+    );
+
+    teardown(vec!["fskv_test_dedup_stats"]);
+}
+
+#[test]
+fn test_rename_map() {
+    let ds = Store::new("fskv_test_rename_map", true).unwrap();
+    assert_eq!(ds.put("from", "shout").is_ok(), true);
+    assert_eq!(ds.put("taken", "bar").is_ok(), true);
+
+    match ds.rename_map("from", "taken", |v| v.to_uppercase()) {
+        Err(FskvError::KeyExists(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("from").unwrap(), "shout");
+    assert_eq!(ds.get("taken").unwrap(), "bar");
+
+    assert_eq!(
+        ds.rename_map("from", "to", |v| v.to_uppercase()).is_ok(),
+        true
+    );
+    assert_eq!(ds.get("to").unwrap(), "SHOUT");
+    match ds.get("from") {
+        Err(FskvError::KeyNotFound(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_rename_map"]);
+}
+
+#[test]
+fn test_modify() {
+    let ds = Store::new("fskv_test_modify", true).unwrap();
+    let increment = |old: Option<String>| {
+        let n: i64 = old.map_or(0, |old| old.parse().unwrap());
+        Some((n + 1).to_string())
+    };
+
+    assert_eq!(
+        ds.modify("count", increment).unwrap(),
+        (None, Some("1".to_string()))
+    );
+    assert_eq!(
+        ds.modify("count", increment).unwrap(),
+        (Some("1".to_string()), Some("2".to_string()))
+    );
+    assert_eq!(ds.get("count").unwrap(), "2");
+
+    // returning None deletes it
+    assert_eq!(
+        ds.modify("count", |_| None).unwrap(),
+        (Some("2".to_string()), None)
+    );
+    assert_eq!(ds.get("count").is_err(), true);
+    assert_eq!(ds.modify("count", |_| None).unwrap(), (None, None));
+
+    teardown(vec!["fskv_test_modify"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_and_dir_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let ds = StoreBuilder::new("fskv_test_file_and_dir_mode")
+        .create(true)
+        .file_mode(0o600)
+        .dir_mode(0o700)
+        .build()
+        .unwrap();
+    ds.put("private", "secret").unwrap();
+    ds.update("updated", "secret").unwrap();
+    ds.append("appended", "secret").unwrap();
+    ds.put_reader_checked("streamed", &b"secret"[..]).unwrap();
+    ds.put_with_variants("compressed", "secret").unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    for key in &["private", "updated", "appended", "streamed", "compressed"] {
+        let file = key_file("fskv_test_file_and_dir_mode", key);
+        assert_eq!(mode(&file), 0o600);
+        // every directory created for the key, up to the root
+        let mut dir = file.parent().unwrap();
+        while dir != Path::new("fskv_test_file_and_dir_mode") {
+            assert_eq!(mode(dir), 0o700);
+            dir = dir.parent().unwrap();
+        }
+    }
+
+    teardown(vec!["fskv_test_file_and_dir_mode"]);
+}
+
+#[test]
+fn test_latency_percentiles() {
+    let ds = StoreBuilder::new("fskv_test_latency_percentiles")
+        .create(true)
+        .record_latencies(true)
+        .build()
+        .unwrap();
+    for i in 0..200 {
+        ds.put(&format!("key{}", i), "value").unwrap();
+        ds.get(&format!("key{}", i)).unwrap();
+    }
+
+    for op in &[OpKind::Get, OpKind::Put] {
+        let latency = ds.latency_percentiles(*op);
+        assert_eq!(latency.count, 200);
+        assert!(latency.p50 > Duration::from_nanos(0));
+        assert!(latency.p50 <= latency.p95);
+        assert!(latency.p95 <= latency.p99);
+        assert!(latency.p99 < Duration::from_secs(5));
+    }
+    assert_eq!(
+        ds.latency_percentiles(OpKind::Delete),
+        Percentiles::default()
+    );
+
+    // nothing's recorded unless asked to
+    let ds = Store::new("fskv_test_latency_percentiles", false).unwrap();
+    ds.get("key0").unwrap();
+    assert_eq!(ds.latency_percentiles(OpKind::Get).count, 0);
+
+    teardown(vec!["fskv_test_latency_percentiles"]);
+}
+
+#[test]
+fn test_find_orphans() {
+    let ds = StoreBuilder::new("fskv_test_find_orphans")
+        .create(true)
+        .versioned(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("fine", "foo").is_ok(), true);
+    assert_eq!(ds.put("unversioned", "foo").is_ok(), true);
+    assert_eq!(ds.find_orphans(false).unwrap(), Vec::<PathBuf>::new());
+
+    // a version left behind by a key that's gone, and a key without one
+    let file = key_file("fskv_test_find_orphans", "unversioned");
+    let bucket = file.parent().unwrap();
+    fs::write(bucket.join(".gone.version"), "3").unwrap();
+    fs::remove_file(bucket.join(".unversioned.version")).unwrap();
+
+    let root = ds
+        .root_path()
+        .join(bucket.strip_prefix("fskv_test_find_orphans").unwrap());
+    let orphans = vec![root.join(".gone.version"), root.join("unversioned")];
+    assert_eq!(ds.find_orphans(false).unwrap(), orphans);
+    assert_eq!(bucket.join(".gone.version").exists(), true);
+
+    // repairing removes the sidecar, and the lock taken to do it, but not
+    // the key
+    assert_eq!(ds.find_orphans(true).unwrap(), orphans);
+    assert_eq!(bucket.join(".gone.version").exists(), false);
+    assert_eq!(bucket.join(".gone.lock").exists(), false);
+    assert_eq!(ds.get("unversioned").unwrap(), "foo");
+    assert_eq!(
+        ds.find_orphans(false).unwrap(),
+        vec![root.join("unversioned")]
+    );
+
+    // deleting a key leaves its lock behind
+    let file = key_file("fskv_test_find_orphans", "fine");
+    let lock = fs::canonicalize(file.parent().unwrap())
+        .unwrap()
+        .join(".fine.lock");
+    assert_eq!(ds.delete("fine").is_ok(), true);
+    let mut orphans = vec![lock.clone(), root.join("unversioned")];
+    orphans.sort();
+    assert_eq!(ds.find_orphans(true).unwrap(), orphans);
+    assert_eq!(lock.exists(), false);
+    assert_eq!(ds.put("fine", "again").is_ok(), true);
+    assert_eq!(ds.get("fine").unwrap(), "again");
+
+    teardown(vec!["fskv_test_find_orphans"]);
+}
+
+#[test]
+fn test_get_deadline() {
+    let ds = Store::new("fskv_test_get_deadline", true).unwrap();
+    let value = "x".repeat(1 << 20);
+    assert_eq!(ds.put("large", &value).is_ok(), true);
+
+    let past = Instant::now() - Duration::from_millis(1);
+    match ds.get_deadline("large", past) {
+        Err(FskvError::Timeout(key)) => assert_eq!(key, "large"),
+        r => panic!("unexpected result: {:?}", r.map(|v| v.len())),
+    }
+    let future = Instant::now() + Duration::from_secs(60);
+    assert_eq!(ds.get_deadline("large", future).unwrap(), value);
+    assert_eq!(
+        ds.get_deadline("missing", future)
+            .unwrap_err()
+            .is_not_found(),
+        true
+    );
+
+    teardown(vec!["fskv_test_get_deadline"]);
+}
+
+#[test]
+fn test_merge_from() {
+    let ours = Store::new("fskv_test_merge_from_ours", true).unwrap();
+    let theirs = Store::new("fskv_test_merge_from_theirs", true).unwrap();
+    assert_eq!(ours.put("mine", "1").is_ok(), true);
+    assert_eq!(ours.put("both", "2").is_ok(), true);
+    assert_eq!(theirs.put("both", "3").is_ok(), true);
+    assert_eq!(theirs.put("theirs", "4").is_ok(), true);
+
+    let merged = ours
+        .merge_from(&theirs, |key, mine, theirs| {
+            assert_eq!(key, "both");
+            format!("{}+{}", mine, theirs)
+        })
+        .unwrap();
+    assert_eq!(merged, 2);
+    assert_eq!(ours.get("mine").unwrap(), "1");
+    assert_eq!(ours.get("both").unwrap(), "2+3");
+    assert_eq!(ours.get("theirs").unwrap(), "4");
+    // the other store is left alone
+    assert_eq!(theirs.get("both").unwrap(), "3");
+    assert_eq!(theirs.get("mine").is_ok(), false);
+
+    teardown(vec![
+        "fskv_test_merge_from_ours",
+        "fskv_test_merge_from_theirs",
+    ]);
+}
+
+#[test]
+fn test_update_over_directory() {
+    let ds = StoreBuilder::new("fskv_test_update_over_directory")
+        .create(true)
+        .verify_renames(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.update("fine", "foo").is_ok(), true);
+    assert_eq!(ds.get("fine").unwrap(), "foo");
+
+    // someone else put a directory where the key's file goes
+    assert_eq!(ds.put("taken", "foo").is_ok(), true);
+    let file = key_file("fskv_test_update_over_directory", "taken");
+    fs::remove_file(&file).unwrap();
+    fs::create_dir(&file).unwrap();
+    fs::write(file.join("something"), "else").unwrap();
+    match ds.update("taken", "foo") {
+        Err(FskvError::CorruptStore(reason)) => {
+            assert_eq!(reason.contains("isn't a regular file"), true)
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // and the temporary file didn't stay behind
+    let leftovers = fs::read_dir(file.parent().unwrap())
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().file_name() != "taken")
+        .count();
+    assert_eq!(leftovers, 0);
+
+    teardown(vec!["fskv_test_update_over_directory"]);
+}
+
+#[test]
+fn test_iter_by_size() {
+    let ds = Store::new("fskv_test_iter_by_size", true).unwrap();
+    for (key, value) in [
+        ("medium", "xx"),
+        ("large", "xxxx"),
+        ("small", "x"),
+        ("also_medium", "yy"),
+    ] {
+        assert_eq!(ds.put(key, value).is_ok(), true);
+    }
+
+    let ascending = ds
+        .iter_by_size(true)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        ascending,
+        vec![
+            ("small".to_string(), 1),
+            ("also_medium".to_string(), 2),
+            ("medium".to_string(), 2),
+            ("large".to_string(), 4),
+        ]
+    );
+    let descending = ds
+        .iter_by_size(false)
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(descending, vec!["large", "also_medium", "medium", "small"]);
+
+    teardown(vec!["fskv_test_iter_by_size"]);
+}
+
+// fails renames, creating directories, or writing new files once they've
+// been created, for as long as it is told to; or the next few renames, as
+// if someone else had the file open, or as if they weren't allowed; or the
+// next few directories, as if interrupted
+#[derive(Default)]
+struct FaultyBackend {
+    fail_renames: AtomicBool,
+    fail_dirs: AtomicBool,
+    interrupted_dirs: AtomicUsize,
+    rename_conflicts: AtomicUsize,
+    denied_renames: AtomicUsize,
+    fail_writes: AtomicBool,
+}
+
+impl Backend for FaultyBackend {
+    fn open(&self, path: &Path, options: &fs::OpenOptions) -> std::io::Result<fs::File> {
+        let file = FsBackend.open(path, options)?;
+        match self.fail_writes.load(Ordering::SeqCst) && file.metadata()?.len() == 0 {
+            true => Err(std::io::Error::other("injected")),
+            false => Ok(file),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+        let interrupted = self
+            .interrupted_dirs
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if interrupted {
+            return Err(std::io::Error::from(std::io::ErrorKind::Interrupted));
+        }
+        match self.fail_dirs.load(Ordering::SeqCst) {
+            true => Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+            false => FsBackend.create_dir_all(path, mode),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let conflict = self
+            .rename_conflicts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if conflict {
+            return Err(std::io::Error::from(std::io::ErrorKind::ResourceBusy));
+        }
+        let denied = self
+            .denied_renames
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if denied {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        }
+        match self.fail_renames.load(Ordering::SeqCst) {
+            true => Err(std::io::Error::other("injected")),
+            false => FsBackend.rename(from, to),
+        }
+    }
+}
+
+#[test]
+fn test_backend() {
+    let backend = Arc::new(FaultyBackend::default());
+    let ds = StoreBuilder::new("fskv_test_backend")
+        .create(true)
+        .max_keys(2)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("existing", "old").is_ok(), true);
+
+    // a failed rename leaves the old value, and no temporary file, behind
+    backend.fail_renames.store(true, Ordering::SeqCst);
+    assert_eq!(ds.update("existing", "new").is_ok(), false);
+    assert_eq!(ds.get("existing").unwrap(), "old");
+    let bucket = key_file("fskv_test_backend", "existing");
+    let files = fs::read_dir(bucket.parent().unwrap()).unwrap().count();
+    assert_eq!(files, 1);
+    // nor does it take up room for a new key
+    assert_eq!(ds.update("new", "new").is_ok(), false);
+    backend.fail_renames.store(false, Ordering::SeqCst);
+    assert_eq!(ds.update("new", "new").is_ok(), true);
+    assert_eq!(ds.get("new").unwrap(), "new");
+
+    // nor does a put that couldn't create its bucket
+    backend.fail_dirs.store(true, Ordering::SeqCst);
+    assert_eq!(ds.put("unborn", "foo").is_ok(), false);
+    backend.fail_dirs.store(false, Ordering::SeqCst);
+    assert_eq!(ds.delete("new").is_ok(), true);
+    assert_eq!(ds.put("unborn", "foo").is_ok(), true);
+
+    teardown(vec!["fskv_test_backend"]);
+}
+
+// reads a corrupt copy of a value instead of the real one, as many times as
+// it is told to
+struct FlakyReadBackend {
+    decoy: PathBuf,
+    corrupt_reads: AtomicUsize,
+}
+
+impl Backend for FlakyReadBackend {
+    fn open(&self, path: &Path, options: &fs::OpenOptions) -> std::io::Result<fs::File> {
+        let corrupt = self
+            .corrupt_reads
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        match corrupt && path.ends_with("flaky") {
+            true => options.open(&self.decoy),
+            false => options.open(path),
+        }
+    }
+}
+
+#[test]
+fn test_get_checked() {
+    let decoy = PathBuf::from("fskv_test_get_checked_decoy");
+    let ds = Store::new("fskv_test_get_checked", true).unwrap();
+    ds.put_reader_checked("flaky", &b"the value"[..]).unwrap();
+    // a copy that differs in content only, which isn't told apart from
+    // the real thing until its checksum is checked
+    let file = key_file("fskv_test_get_checked", "flaky");
+    let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+    fs::write(&decoy, "the vaXue").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&decoy)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let backend = Arc::new(FlakyReadBackend {
+        decoy: decoy.clone(),
+        corrupt_reads: AtomicUsize::new(1),
+    });
+    // by default one bad read is enough to fail
+    let ds = StoreBuilder::new("fskv_test_get_checked")
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    assert_eq!(ds.get_checked("flaky").is_ok(), false);
+    assert_eq!(ds.get_checked("flaky").unwrap(), "the value");
+
+    // but it can be read again instead
+    backend.corrupt_reads.store(2, Ordering::SeqCst);
+    let ds = StoreBuilder::new("fskv_test_get_checked")
+        .checksum_retries(2)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    assert_eq!(ds.get_checked("flaky").unwrap(), "the value");
+    assert_eq!(backend.corrupt_reads.load(Ordering::SeqCst), 0);
+    // values that are corrupt on disk still fail, once the retries are up
+    fs::rename(&decoy, &file).unwrap();
+    assert_eq!(ds.get_checked("flaky").is_ok(), false);
+
+    teardown(vec!["fskv_test_get_checked"]);
+}
+
+#[test]
+fn test_manifest() {
+    let ds = Store::new("fskv_test_manifest", true).unwrap();
+    assert_eq!(ds.put("b", "two").is_ok(), true);
+    assert_eq!(ds.put("a", "one").is_ok(), true);
+    assert_eq!(ds.put("c", "three").is_ok(), true);
+
+    let before = ds.manifest().unwrap();
+    assert_eq!(
+        before,
+        vec![
+            ("a".to_string(), 3, crc32fast::hash(b"one")),
+            ("b".to_string(), 3, crc32fast::hash(b"two")),
+            ("c".to_string(), 5, crc32fast::hash(b"three")),
+        ]
+    );
+
+    // same size, different content
+    assert_eq!(ds.update("b", "TWO").is_ok(), true);
+    let after = ds.manifest().unwrap();
+    let changed = before
+        .iter()
+        .zip(&after)
+        .filter(|(before, after)| before != after)
+        .map(|(_, (key, _, _))| key.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(changed, vec!["b"]);
+    assert_eq!(after[1].1, 3);
+
+    teardown(vec!["fskv_test_manifest"]);
+}
+
+#[test]
+fn test_lease() {
+    let ds = Store::new("fskv_test_lease", true).unwrap();
+    let ttl = Duration::from_millis(200);
+    assert_eq!(ds.lease("job", "first", ttl).unwrap(), true);
+    // someone else can't take it, renew it or release it
+    assert_eq!(ds.lease("job", "second", ttl).unwrap(), false);
+    assert_eq!(ds.renew("job", "second", ttl).unwrap(), false);
+    assert_eq!(ds.release("job", "second").unwrap(), false);
+    assert_eq!(ds.renew("job", "first", ttl).unwrap(), true);
+
+    // until it expires
+    thread::sleep(ttl * 2);
+    assert_eq!(ds.renew("job", "first", ttl).unwrap(), false);
+    assert_eq!(ds.lease("job", "second", ttl).unwrap(), true);
+    assert_eq!(ds.lease("job", "first", ttl).unwrap(), false);
+
+    // or it's released
+    assert_eq!(ds.release("job", "second").unwrap(), true);
+    assert_eq!(ds.get("job").is_ok(), false);
+    assert_eq!(ds.lease("job", "first", ttl).unwrap(), true);
+
+    // other values aren't leases
+    assert_eq!(ds.put("plain", "foo").is_ok(), true);
+    match ds.lease("plain", "first", ttl) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_lease"]);
+}
+
+#[test]
+fn test_disk_usage_delta() {
+    let ds = StoreBuilder::new("fskv_test_disk_usage_delta")
+        .create(true)
+        .validate_on_open(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("a", "12345").is_ok(), true);
+    assert_eq!(ds.disk_usage().unwrap(), 5);
+    // counting from nothing the first time
+    assert_eq!(ds.disk_usage_delta().unwrap(), 5);
+    assert_eq!(ds.disk_usage_delta().unwrap(), 0);
+
+    assert_eq!(ds.put("b", "1234567890").is_ok(), true);
+    assert_eq!(ds.update("a", "123").is_ok(), true);
+    assert_eq!(ds.disk_usage_delta().unwrap(), 8);
+    assert_eq!(ds.delete("b").is_ok(), true);
+    assert_eq!(ds.disk_usage_delta().unwrap(), -10);
+
+    // the baseline is kept on disk, where it doesn't trip validation
+    let ds = StoreBuilder::new("fskv_test_disk_usage_delta")
+        .validate_on_open(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("c", "1").is_ok(), true);
+    assert_eq!(ds.disk_usage_delta().unwrap(), 1);
+
+    teardown(vec!["fskv_test_disk_usage_delta"]);
+}
+
+#[test]
+fn test_append_record() {
+    let ds = Store::new("fskv_test_append_record", true).unwrap();
+    for record in ["first", "second", ""] {
+        assert_eq!(ds.append_record("log", record).is_ok(), true);
+    }
+    assert_eq!(ds.get("log").unwrap(), "first\nsecond\n\n");
+    let records = ds
+        .read_records("log")
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records, vec!["first", "second", ""]);
+    // records can't hold the delimiter
+    assert_eq!(ds.append_record("log", "two\nlines").is_ok(), false);
+
+    let ds = StoreBuilder::new("fskv_test_append_record")
+        .record_delimiter("\r\n--\r\n")
+        .build()
+        .unwrap();
+    for record in ["one\nline", "two\r\n-", "three"] {
+        assert_eq!(ds.append_record("multiline", record).is_ok(), true);
+    }
+    let records = ds
+        .read_records("multiline")
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records, vec!["one\nline", "two\r\n-", "three"]);
+    assert_eq!(ds.append_record("multiline", "four\r\n--").is_ok(), false);
+    assert_eq!(
+        StoreBuilder::new("fskv_test_append_record")
+            .record_delimiter("")
+            .build()
+            .is_ok(),
+        false
+    );
+
+    teardown(vec!["fskv_test_append_record"]);
+}
+
+#[test]
+fn test_snapshot_and_clear() {
+    let ds = Store::new("fskv_test_snapshot_and_clear", true).unwrap();
+    assert_eq!(ds.snapshot_and_clear("buffer").unwrap(), None);
+
+    let writers = (0..4)
+        .map(|w| {
+            let ds = ds.clone();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    ds.append_record("buffer", &format!("{}-{}", w, i)).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut shipped = Vec::new();
+    while !writers.iter().all(|w| w.is_finished()) {
+        if let Some(value) = ds.snapshot_and_clear("buffer").unwrap() {
+            shipped.push(value);
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    shipped.push(ds.snapshot_and_clear("buffer").unwrap().unwrap());
+    assert_eq!(ds.get("buffer").unwrap(), "");
+
+    // every record was shipped, exactly once
+    let mut records = shipped
+        .concat()
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    records.sort();
+    let mut expected = (0..4)
+        .flat_map(|w| (0..50).map(move |i| format!("{}-{}", w, i)))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(records, expected);
+
+    teardown(vec!["fskv_test_snapshot_and_clear"]);
+}
+
+// keeps track of the most files being opened at once
+#[derive(Default)]
+struct CountingBackend {
+    opening: AtomicUsize,
+    most: AtomicUsize,
+}
+
+impl Backend for CountingBackend {
+    fn open(&self, path: &Path, options: &fs::OpenOptions) -> std::io::Result<fs::File> {
+        let opening = self.opening.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most.fetch_max(opening, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(1));
+        let file = options.open(path);
+        self.opening.fetch_sub(1, Ordering::SeqCst);
+        file
+    }
+}
+
+#[test]
+fn test_max_open_files() {
+    let backend = Arc::new(CountingBackend::default());
+    let ds = StoreBuilder::new("fskv_test_max_open_files")
+        .create(true)
+        .max_open_files(2)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    let keys = (0..100).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+    for key in &keys {
+        assert_eq!(ds.put(key, key).is_ok(), true);
+    }
+    backend.most.store(0, Ordering::SeqCst);
+
+    // two bulk reads at once share the limit
+    let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+    thread::scope(|scope| {
+        let readers = (0..2)
+            .map(|_| scope.spawn(|| ds.get_many_parallel(&keys, 16)))
+            .collect::<Vec<_>>();
+        for reader in readers {
+            let values = reader.join().unwrap();
+            assert_eq!(values.len(), keys.len());
+            for (key, value) in keys.iter().zip(values) {
+                assert_eq!(value.unwrap(), *key);
+            }
+        }
+    });
+    assert_eq!(backend.most.load(Ordering::SeqCst) <= 2, true);
+
+    // as does going through all of them
+    backend.most.store(0, Ordering::SeqCst);
+    let seen = AtomicUsize::new(0);
+    ds.par_for_each(16, |_, _| {
+        seen.fetch_add(1, Ordering::SeqCst);
+    })
+    .unwrap();
+    assert_eq!(seen.into_inner(), keys.len());
+    assert_eq!(backend.most.load(Ordering::SeqCst) <= 2, true);
+
+    teardown(vec!["fskv_test_max_open_files"]);
+}
+
+#[test]
+fn test_get_validated() {
+    let ds = Store::new("fskv_test_get_validated", true).unwrap();
+    let token = ds.put_with_token("key", "first").unwrap();
+    assert_eq!(
+        ds.get_validated("key", token).unwrap(),
+        GetResult::Unchanged
+    );
+
+    assert_eq!(ds.update("key", "second").is_ok(), true);
+    assert_eq!(
+        ds.get_validated("key", token).unwrap(),
+        GetResult::Modified("second".to_string())
+    );
+    let token = ds.update_with_token("key", "third").unwrap();
+    assert_eq!(
+        ds.get_validated("key", token).unwrap(),
+        GetResult::Unchanged
+    );
+    // rewriting the same value counts as a change too
+    assert_eq!(ds.update("key", "third").is_ok(), true);
+    assert_eq!(
+        ds.get_validated("key", token).unwrap(),
+        GetResult::Modified("third".to_string())
+    );
+    assert_eq!(ds.delete("key").is_ok(), true);
+    assert_eq!(
+        ds.get_validated("key", token).unwrap_err().is_not_found(),
+        true
+    );
+
+    teardown(vec!["fskv_test_get_validated"]);
+}
+
+#[test]
+fn test_empty_is_absent() {
+    let ds = Store::new("fskv_test_empty_is_absent", true).unwrap();
+    assert_eq!(ds.put("empty", "").is_ok(), true);
+    assert_eq!(ds.put("full", "foo").is_ok(), true);
+    // empty values are values like any other by default
+    assert_eq!(ds.contains_key("empty").unwrap(), true);
+    assert_eq!(ds.get("empty").unwrap(), "");
+    assert_eq!(ds.contains_key("missing").unwrap(), false);
+
+    let ds = StoreBuilder::new("fskv_test_empty_is_absent")
+        .empty_is_absent(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.contains_key("empty").unwrap(), false);
+    match ds.get("empty") {
+        Err(FskvError::KeyNotFound(key)) => assert_eq!(key, "empty"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get_bytes("empty").unwrap_err().is_not_found(), true);
+    assert_eq!(ds.contains_key("full").unwrap(), true);
+    assert_eq!(ds.get("full").unwrap(), "foo");
+    // scans that read values pass over them
+    assert_eq!(ds.search_values("", None).unwrap(), vec!["full"]);
+    assert_eq!(ds.concat_prefix("", ",").unwrap(), "foo");
+    assert_eq!(ds.fingerprint().is_ok(), true);
+    // emptying a value is then as good as deleting it
+    assert_eq!(ds.update("full", "").is_ok(), true);
+    assert_eq!(ds.contains_key("full").unwrap(), false);
+
+    teardown(vec!["fskv_test_empty_is_absent"]);
+}
+
+#[test]
+fn test_rebuild_index() {
+    let ds = StoreBuilder::new("fskv_test_rebuild_index")
+        .create(true)
+        .max_keys(3)
+        .build()
+        .unwrap();
+    assert_eq!(ds.is_empty().unwrap(), true);
+    assert_eq!(ds.put("a", "1").is_ok(), true);
+    assert_eq!(ds.put("b", "2").is_ok(), true);
+    assert_eq!(ds.approx_len().unwrap(), 2);
+    assert_eq!(ds.is_empty().unwrap(), false);
+
+    // the count drifts, e.g. after keys were removed behind the store's back
+    fs::remove_file(key_file("fskv_test_rebuild_index", "b")).unwrap();
+    assert_eq!(ds.approx_len().unwrap(), 2);
+    assert_eq!(ds.len().unwrap(), 1);
+    ds.rebuild_index().unwrap();
+    assert_eq!(ds.approx_len().unwrap(), 1);
+
+    // or got corrupt
+    fs::write(ds.root_path().join(".fskv.len"), "garbage").unwrap();
+    assert_eq!(ds.approx_len().is_ok(), false);
+    assert_eq!(ds.put("c", "3").is_ok(), false);
+    ds.rebuild_index().unwrap();
+    assert_eq!(ds.approx_len().unwrap(), ds.len().unwrap());
+    assert_eq!(ds.put("c", "3").is_ok(), true);
+    assert_eq!(ds.put("d", "4").is_ok(), true);
+    assert_eq!(ds.put("e", "5").is_ok(), false);
+
+    teardown(vec!["fskv_test_rebuild_index"]);
+}
+
+#[test]
+fn test_get_or_create() {
+    let ds = Store::new("fskv_test_get_or_create", true).unwrap();
+    let results = (0..8)
+        .map(|i| {
+            let ds = ds.clone();
+            thread::spawn(move || ds.get_or_create("once", &format!("value {}", i)).unwrap())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .collect::<Vec<_>>();
+
+    // exactly one of them created it, and they all got its value
+    assert_eq!(results.iter().filter(|(_, created)| *created).count(), 1);
+    let value = ds.get("once").unwrap();
+    assert_eq!(results.iter().all(|(v, _)| *v == value), true);
+    assert_eq!(ds.get_or_create("once", "other").unwrap(), (value, false));
+
+    teardown(vec!["fskv_test_get_or_create"]);
+}
+
+#[test]
+fn test_par_for_each() {
+    let ds = Store::new("fskv_test_par_for_each", true).unwrap();
+    for i in 0..200 {
+        let value = format!("{{\"n\": {}}}", i);
+        assert_eq!(ds.put(&format!("key{}", i), &value).is_ok(), true);
+    }
+    let n = |value: &str| -> u64 {
+        value
+            .trim_start_matches("{\"n\": ")
+            .trim_end_matches('}')
+            .parse()
+            .unwrap()
+    };
+
+    let mut expected = 0;
+    for key in ds.keys() {
+        expected += n(&ds.get(&key.unwrap()).unwrap());
+    }
+    let total = AtomicU64::new(0);
+    let seen = AtomicUsize::new(0);
+    ds.par_for_each(4, |key, value| {
+        assert_eq!(key.starts_with("key"), true);
+        total.fetch_add(n(value), Ordering::SeqCst);
+        seen.fetch_add(1, Ordering::SeqCst);
+    })
+    .unwrap();
+    assert_eq!(total.into_inner(), expected);
+    assert_eq!(seen.into_inner(), 200);
+
+    teardown(vec!["fskv_test_par_for_each"]);
+}
+
+#[test]
+fn test_put_with_eviction() {
+    let ds = StoreBuilder::new("fskv_test_put_with_eviction")
+        .create(true)
+        .max_keys(2)
+        .build()
+        .unwrap();
+    // evict the oldest key first
+    let mut keys = std::collections::VecDeque::new();
+    for key in ["a", "b", "c", "d"] {
+        ds.put_with_eviction(key, key, || keys.pop_front()).unwrap();
+        keys.push_back(key.to_string());
+    }
+    assert_eq!(ds.get("a").is_ok(), false);
+    assert_eq!(ds.get("b").is_ok(), false);
+    assert_eq!(ds.get("c").unwrap(), "c");
+    assert_eq!(ds.get("d").unwrap(), "d");
+
+    // without a victim it's still full
+    match ds.put_with_eviction("e", "e", || None) {
+        Err(FskvError::Full) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("c").unwrap(), "c");
+
+    // nothing is evicted to put a key that already exists
+    match ds.put_with_eviction("c", "new", || Some("d".to_string())) {
+        Err(FskvError::KeyExists(key)) => assert_eq!(key, "c"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("c").unwrap(), "c");
+    assert_eq!(ds.get("d").unwrap(), "d");
+
+    teardown(vec!["fskv_test_put_with_eviction"]);
+}
+
+#[test]
+fn test_audit_filenames() {
+    let ds = StoreBuilder::new("fskv_test_audit_filenames")
+        .create(true)
+        .versioned(true)
+        .value_extension(".json")
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("a", "{}").is_ok(), true);
+    ds.put_reader_checked("b", &b"{}"[..]).unwrap();
+    assert_eq!(ds.audit_filenames().unwrap(), Vec::<PathBuf>::new());
+
+    // things that aren't keys, in a bucket and above them
+    let file = key_file("fskv_test_audit_filenames", "a.json");
+    let bucket = ds.root_path().join(
+        file.parent()
+            .unwrap()
+            .strip_prefix("fskv_test_audit_filenames")
+            .unwrap(),
+    );
+    fs::write(bucket.join("notes.txt"), "").unwrap();
+    fs::write(bucket.join(".1234.tmp"), "").unwrap();
+    fs::create_dir(bucket.join("c.json")).unwrap();
+    fs::write(ds.root_path().join("junk"), "").unwrap();
+    assert_eq!(
+        ds.audit_filenames().unwrap(),
+        vec![
+            bucket.join(".1234.tmp"),
+            bucket.join("c.json"),
+            bucket.join("notes.txt"),
+            ds.root_path().join("junk"),
+        ]
+    );
+    assert_eq!(ds.get("a").unwrap(), "{}");
+
+    teardown(vec!["fskv_test_audit_filenames"]);
+}
+
+// keeps every record logged, from all the tests running at the time
+struct CapturingLogger {
+    records: Mutex<Vec<(log::Level, String)>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let message = record.args().to_string();
+        self.records.lock().unwrap().push((record.level(), message));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+// the records logged so far mentioning `key`
+fn logged(key: &str) -> Vec<(log::Level, String)> {
+    let key = format!("{:?}", key);
+    LOGGER
+        .records
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, message)| message.contains(&key))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_logging() {
+    // it can only be set once per process
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+    let ds = Store::new("fskv_test_logging", true).unwrap();
+    assert_eq!(ds.put("logged_put", "foo").is_ok(), true);
+    let records = logged("logged_put");
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].0, log::Level::Trace);
+    assert_eq!(
+        records[0].1.starts_with("put \"logged_put\": creating "),
+        true
+    );
+    assert_eq!(
+        records[1],
+        (
+            log::Level::Trace,
+            "put \"logged_put\": wrote 3 bytes".to_string()
+        )
+    );
+
+    // failures are warnings
+    assert_eq!(ds.put("logged_update", "foo").is_ok(), true);
+    let file = key_file("fskv_test_logging", "logged_update");
+    fs::remove_file(&file).unwrap();
+    fs::create_dir(&file).unwrap();
+    assert_eq!(ds.update("logged_update", "bar").is_ok(), false);
+    let warnings = logged("logged_update")
+        .into_iter()
+        .filter(|(level, _)| *level == log::Level::Warn)
+        .count();
+    assert_eq!(warnings, 1);
+
+    teardown(vec!["fskv_test_logging"]);
+}
+
+#[test]
+fn test_apply() {
+    let ds = Store::new("fskv_test_apply", true).unwrap();
+    let results = ds
+        .apply(&[
+            Op::Put("key", "foo"),
+            Op::Get("key"),
+            Op::Put("key", "bar"),
+            Op::Update("key", "baz"),
+            Op::Get("key"),
+            Op::Delete("key"),
+            Op::Get("key"),
+        ])
+        .unwrap();
+    assert_eq!(results.len(), 7);
+    assert_eq!(matches!(results[0], OpResult::Done), true);
+    assert_eq!(
+        matches!(&results[1], OpResult::Value(v) if v == "foo"),
+        true
+    );
+    // failures don't stop the rest
+    assert_eq!(
+        matches!(results[2], OpResult::Failed(FskvError::KeyExists(_))),
+        true
+    );
+    assert_eq!(matches!(results[3], OpResult::Done), true);
+    assert_eq!(
+        matches!(&results[4], OpResult::Value(v) if v == "baz"),
+        true
+    );
+    assert_eq!(matches!(results[5], OpResult::Done), true);
+    match &results[6] {
+        OpResult::Failed(e) => assert_eq!(e.is_not_found(), true),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // an invalid key anywhere stops all of them
+    match ds.apply(&[Op::Put("fine", "foo"), Op::Delete("../nope")]) {
+        Err(FskvError::InvalidKey(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(ds.get("fine").is_ok(), false);
+
+    teardown(vec!["fskv_test_apply"]);
+}
+
+#[test]
+fn test_get_encoded() {
+    let ds = Store::new("fskv_test_get_encoded", true).unwrap();
+    for (key, value) in [
+        ("a", "h\u{e9}llo \u{1f600}"),
+        ("b", "f"),
+        ("c", "fo"),
+        ("d", "foo"),
+    ] {
+        assert_eq!(ds.put(key, value).is_ok(), true);
+    }
+
+    assert_eq!(
+        ds.get_encoded("a", Encoding::Utf8).unwrap(),
+        "h\u{e9}llo \u{1f600}".as_bytes()
+    );
+    assert_eq!(
+        ds.get_encoded("a", Encoding::Base64).unwrap(),
+        b"aMOpbGxvIPCfmIA="
+    );
+    assert_eq!(ds.get_encoded("b", Encoding::Base64).unwrap(), b"Zg==");
+    assert_eq!(ds.get_encoded("c", Encoding::Base64).unwrap(), b"Zm8=");
+    assert_eq!(ds.get_encoded("d", Encoding::Base64).unwrap(), b"Zm9v");
+    // the emoji takes a surrogate pair
+    assert_eq!(
+        ds.get_encoded("a", Encoding::Utf16Le).unwrap(),
+        [0x68, 0, 0xe9, 0, 0x6c, 0, 0x6c, 0, 0x6f, 0, 0x20, 0, 0x3d, 0xd8, 0x00, 0xde]
+    );
+    assert_eq!(
+        ds.get_encoded("d", Encoding::Utf16Be).unwrap(),
+        [0, 0x66, 0, 0x6f, 0, 0x6f]
+    );
+    assert_eq!(
+        ds.get_encoded("missing", Encoding::Base64)
+            .unwrap_err()
+            .is_not_found(),
+        true
+    );
+
+    teardown(vec!["fskv_test_get_encoded"]);
+}
+
+#[test]
+fn test_update_if_below() {
+    let ds = Store::new("fskv_test_update_if_below", true).unwrap();
+    let granted = (0..10)
+        .map(|_| {
+            let ds = ds.clone();
+            thread::spawn(move || {
+                (0..3)
+                    .filter(|_| ds.update_if_below("quota", 5).unwrap())
+                    .count()
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .sum::<usize>();
+    assert_eq!(granted, 5);
+    assert_eq!(ds.get("quota").unwrap(), "5");
+
+    assert_eq!(ds.reset_counter("quota", 4).is_ok(), true);
+    assert_eq!(ds.update_if_below("quota", 5).unwrap(), true);
+    assert_eq!(ds.update_if_below("quota", 5).unwrap(), false);
+
+    teardown(vec!["fskv_test_update_if_below"]);
+}
+
+#[test]
+fn test_created_at() {
+    let ds = StoreBuilder::new("fskv_test_created_at")
+        .create(true)
+        .creation_times(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("k", "first").is_ok(), true);
+    let created = ds.created_at("k").unwrap();
+
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(ds.update("k", "second").is_ok(), true);
+    assert_eq!(ds.created_at("k").unwrap(), created);
+    assert_eq!(ds.modified_at("k").unwrap() > created, true);
+
+    // keys created other than by put, and renamed keys keeping theirs
+    assert_eq!(ds.update("upserted", "v").is_ok(), true);
+    assert_eq!(ds.append("appended", "v").is_ok(), true);
+    assert_eq!(ds.created_at("upserted").unwrap() > created, true);
+    assert_eq!(ds.created_at("appended").unwrap() > created, true);
+    let upserted = ds.created_at("upserted").unwrap();
+    assert_eq!(ds.rename_key("upserted", "renamed").is_ok(), true);
+    assert_eq!(ds.created_at("renamed").unwrap(), upserted);
+    assert_eq!(ds.rename_key_force("renamed", "appended").is_ok(), true);
+    assert_eq!(ds.created_at("appended").unwrap(), upserted);
+    assert_eq!(ds.delete("appended").is_ok(), true);
+
+    // a new key once deleted, and nothing recorded without the option
+    assert_eq!(ds.delete("k").is_ok(), true);
+    assert_eq!(ds.created_at("k").unwrap_err().is_not_found(), true);
+    assert_eq!(ds.modified_at("k").unwrap_err().is_not_found(), true);
+    let plain = Store::new("fskv_test_created_at", false).unwrap();
+    assert_eq!(plain.put("k", "third").is_ok(), true);
+    assert_eq!(ds.created_at("k").is_err(), true);
+    assert_eq!(ds.audit_filenames().unwrap().is_empty(), true);
+
+    teardown(vec!["fskv_test_created_at"]);
+}
+
+#[test]
+fn test_prefetch() {
+    let ds = Store::new("fskv_test_prefetch", true).unwrap();
+    assert_eq!(ds.put("a", "1").is_ok(), true);
+    assert_eq!(ds.put("b", "2").is_ok(), true);
+
+    assert_eq!(ds.prefetch(&["a", "b", "missing"]).is_ok(), true);
+    assert_eq!(ds.get("a").unwrap(), "1");
+    assert_eq!(ds.get("b").unwrap(), "2");
+    assert_eq!(ds.prefetch(&[""]).is_err(), true);
+
+    teardown(vec!["fskv_test_prefetch"]);
+}
+
+#[test]
+fn test_append_ringbuffer() {
+    let ds = Store::new("fskv_test_append_ringbuffer", true).unwrap();
+    for i in 0..10 {
+        let record = format!("event {}", i);
+        assert_eq!(ds.append_ringbuffer("events", &record, 3).is_ok(), true);
+    }
+    let records = ds
+        .read_records("events")
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records, vec!["event 7", "event 8", "event 9"]);
+    assert_eq!(
+        ds.append_ringbuffer("events", "two\nlines", 3).is_ok(),
+        false
+    );
+
+    assert_eq!(ds.append_ringbuffer("events", "last", 1).is_ok(), true);
+    assert_eq!(ds.get("events").unwrap(), "last\n");
+
+    teardown(vec!["fskv_test_append_ringbuffer"]);
+}
+
+#[test]
+fn test_key_paths() {
+    let ds = Store::new("fskv_test_key_paths", true).unwrap();
+    for key in ["a", "b", "c"] {
+        assert_eq!(ds.put(key, key).is_ok(), true);
+    }
+
+    let root = fs::canonicalize("fskv_test_key_paths").unwrap();
+    let mut keys = Vec::new();
+    for entry in ds.key_paths() {
+        let (key, path) = entry.unwrap();
+        assert_eq!(path.is_file(), true);
+        assert_eq!(path.starts_with(&root), true);
+        assert_eq!(path.file_name().unwrap().to_str().unwrap(), key);
+        assert_eq!(fs::read_to_string(&path).unwrap(), key);
+        keys.push(key);
+    }
+    keys.sort();
+    assert_eq!(keys, vec!["a", "b", "c"]);
+
+    teardown(vec!["fskv_test_key_paths"]);
+}
+
+#[test]
+fn test_checksum_algorithm() {
+    let ds = StoreBuilder::new("fskv_test_checksum_algorithm")
+        .create(true)
+        .checksum_algorithm(ChecksumAlgorithm::Sha256)
+        .build()
+        .unwrap();
+    let checksum = ds.put_reader_checked("k", &b"the value"[..]).unwrap();
+    assert_eq!(
+        checksum,
+        "eb69abcc54d8bcf975aa735ebb61c8ceb27555455d983d1f8881756760db1fcc"
+    );
+    assert_eq!(ds.get_checked("k").unwrap(), "the value");
+
+    // flip a byte in place, leaving its size and mtime alone
+    let file = key_file("fskv_test_checksum_algorithm", "k");
+    let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+    let mut f = fs::OpenOptions::new().write(true).open(&file).unwrap();
+    f.write_all(b"T").unwrap();
+    f.set_modified(mtime).unwrap();
+    drop(f);
+    assert_eq!(ds.get_checked("k").is_err(), true);
+
+    // the algorithm sticks with the store
+    assert_eq!(
+        StoreBuilder::new("fskv_test_checksum_algorithm")
+            .checksum_algorithm(ChecksumAlgorithm::XxHash64)
+            .build()
+            .is_err(),
+        true
+    );
+    let ds = Store::new("fskv_test_checksum_algorithm", false).unwrap();
+    ds.put_reader_checked("other", &b"another value"[..])
+        .unwrap();
+    assert_eq!(ds.get_checked("other").unwrap(), "another value");
+
+    let other = StoreBuilder::new("fskv_test_checksum_algorithm_other")
+        .create(true)
+        .checksum_algorithm(ChecksumAlgorithm::XxHash64)
+        .build()
+        .unwrap();
+    let checksum = other.put_reader_checked("k", &b"the value"[..]).unwrap();
+    assert_eq!(checksum.len(), 16);
+    assert_eq!(other.get_checked("k").unwrap(), "the value");
+
+    teardown(vec![
+        "fskv_test_checksum_algorithm",
+        "fskv_test_checksum_algorithm_other",
+    ]);
+}
+
+#[test]
+fn test_is_locked() {
+    let ds = StoreBuilder::new("fskv_test_is_locked")
+        .create(true)
+        .snapshots(true)
+        .build()
+        .unwrap();
+    assert_eq!(ds.is_locked().unwrap(), false);
+    assert_eq!(ds.put("k", "v").is_ok(), true);
+    assert_eq!(ds.is_locked().unwrap(), false);
+
+    // as another process taking a snapshot would
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (unlock_tx, unlock_rx) = mpsc::channel();
+    let holder = thread::spawn(move || {
+        let file = fs::File::open("fskv_test_is_locked/.fskv.lock").unwrap();
+        file.lock().unwrap();
+        locked_tx.send(()).unwrap();
+        unlock_rx.recv().unwrap();
+    });
+    locked_rx.recv().unwrap();
+    assert_eq!(ds.is_locked().unwrap(), true);
+    unlock_tx.send(()).unwrap();
+    holder.join().unwrap();
+    assert_eq!(ds.is_locked().unwrap(), false);
+
+    teardown(vec!["fskv_test_is_locked"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_patch_json() {
+    use fskv::PatchDepth;
+    use serde_json::json;
+
+    let ds = Store::new("fskv_test_patch_json", true).unwrap();
+    let user = json!({"name": "ana", "age": 30, "address": {"city": "Porto", "zip": "4000"}});
+    assert_eq!(ds.put("user", &user.to_string()).is_ok(), true);
+
+    let patch = json!({"age": 31, "email": "ana@example.com", "address": {"city": "Lisboa"}});
+    assert_eq!(
+        ds.patch_json("user", &patch, PatchDepth::Deep).is_ok(),
+        true
+    );
+    let value: serde_json::Value = serde_json::from_str(&ds.get("user").unwrap()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "name": "ana",
+            "age": 31,
+            "email": "ana@example.com",
+            "address": {"city": "Lisboa", "zip": "4000"}
+        })
+    );
+
+    // shallow patches replace nested objects as a whole
+    assert_eq!(
+        ds.patch_json("user", &patch, PatchDepth::Shallow).is_ok(),
+        true
+    );
+    let value: serde_json::Value = serde_json::from_str(&ds.get("user").unwrap()).unwrap();
+    assert_eq!(value["address"], json!({"city": "Lisboa"}));
+    assert_eq!(value["name"], "ana");
+
+    match ds.patch_json("user", &json!([1]), PatchDepth::Deep) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(
+        ds.patch_json("missing", &patch, PatchDepth::Deep)
+            .unwrap_err()
+            .is_not_found(),
+        true
+    );
+
+    teardown(vec!["fskv_test_patch_json"]);
+}
+
+#[test]
+fn test_update_retries() {
+    let backend = Arc::new(FaultyBackend::default());
+    let ds = StoreBuilder::new("fskv_test_update_retries")
+        .create(true)
+        .update_retries(3)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+    assert_eq!(ds.put("k", "old").is_ok(), true);
+
+    // waiting a little longer before each retry
+    backend.rename_conflicts.store(3, Ordering::SeqCst);
+    let start = Instant::now();
+    assert_eq!(ds.update("k", "new").is_ok(), true);
+    assert_eq!(start.elapsed() >= Duration::from_millis(1 + 2 + 4), true);
+    assert_eq!(ds.get("k").unwrap(), "new");
+    assert_eq!(backend.rename_conflicts.load(Ordering::SeqCst), 0);
+
+    // only so many times, and not for errors that won't go away
+    backend.rename_conflicts.store(4, Ordering::SeqCst);
+    assert_eq!(ds.update("k", "newer").is_ok(), false);
+    assert_eq!(ds.get("k").unwrap(), "new");
+    backend.rename_conflicts.store(0, Ordering::SeqCst);
+    backend.fail_renames.store(true, Ordering::SeqCst);
+    assert_eq!(ds.update("k", "newer").is_ok(), false);
+    backend.fail_renames.store(false, Ordering::SeqCst);
+    #[cfg(not(windows))]
+    {
+        backend.denied_renames.store(2, Ordering::SeqCst);
+        assert_eq!(ds.update("k", "newer").is_ok(), false);
+        assert_eq!(backend.denied_renames.load(Ordering::SeqCst), 1);
+        backend.denied_renames.store(0, Ordering::SeqCst);
+    }
+    // a put never replaces a key, however many times it's tried
+    assert_eq!(ds.put("k", "newer").is_ok(), false);
+
+    teardown(vec!["fskv_test_update_retries"]);
+}
+
+#[test]
+fn test_suggest_depth() {
+    let ds = Store::new("fskv_test_suggest_depth", true).unwrap();
+    assert_eq!(ds.suggest_depth(1).unwrap(), 1);
+    for i in 0..100 {
+        assert_eq!(ds.put(&format!("key{}", i), "v").is_ok(), true);
+    }
+    // 100 keys fit in 65536 buckets, even at one per bucket
+    assert_eq!(ds.suggest_depth(1).unwrap(), 1);
+    assert_eq!(ds.suggest_depth(100).unwrap(), 1);
+    match ds.suggest_depth(0) {
+        Err(FskvError::InvalidValue(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec!["fskv_test_suggest_depth"]);
+}
+
+#[test]
+fn test_put_with_variants() {
+    let ds = Store::new("fskv_test_put_with_variants", true).unwrap();
+    let value = "body { color: red; }\n".repeat(100);
+    assert_eq!(ds.put_with_variants("style.css", &value).is_ok(), true);
+    assert_eq!(ds.get("style.css").unwrap(), value);
+
+    let gzipped = ds
+        .get_variant("style.css", ContentEncoding::Gzip)
+        .unwrap()
+        .unwrap();
+    assert_eq!(gzipped.len() < value.len(), true);
+    let mut gunzipped = String::new();
+    flate2::read::GzDecoder::new(&gzipped[..])
+        .read_to_string(&mut gunzipped)
+        .unwrap();
+    assert_eq!(gunzipped, value);
+    let brotli = ds
+        .get_variant("style.css", ContentEncoding::Brotli)
+        .unwrap()
+        .unwrap();
+    let mut decompressed = String::new();
+    brotli::Decompressor::new(&brotli[..], 4096)
+        .read_to_string(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, value);
+    let zstd = ds
+        .get_variant("style.css", ContentEncoding::Zstd)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        zstd::stream::decode_all(&zstd[..]).unwrap(),
+        value.as_bytes()
+    );
+
+    // there are none for values written otherwise, or since
+    assert_eq!(ds.put_with_variants("style.css", "other").is_ok(), false);
+    assert_eq!(ds.put("plain", "value").is_ok(), true);
+    assert_eq!(
+        ds.get_variant("plain", ContentEncoding::Gzip).unwrap(),
+        None
+    );
+    assert_eq!(ds.update("style.css", "body {}").is_ok(), true);
+    assert_eq!(
+        ds.get_variant("style.css", ContentEncoding::Gzip).unwrap(),
+        None
+    );
+    assert_eq!(
+        ds.get_variant("missing", ContentEncoding::Gzip)
+            .unwrap_err()
+            .is_not_found(),
+        true
+    );
+
+    // the copies go away with the key, however it's removed
+    assert_eq!(ds.put_with_variants("deleted", &value).is_ok(), true);
+    assert_eq!(ds.delete("deleted").is_ok(), true);
+    assert_eq!(ds.put_with_variants("taken", &value).is_ok(), true);
+    assert_eq!(ds.take_once("taken").unwrap(), Some(value.clone()));
+    assert_eq!(ds.put_with_variants("renamed", &value).is_ok(), true);
+    let bucket = key_file("fskv_test_put_with_variants", "renamed");
+    let bucket = fs::canonicalize(bucket.parent().unwrap()).unwrap();
+    assert_eq!(ds.rename_key_force("renamed", "plain").is_ok(), true);
+    // all but the lock taken to rename it
+    assert_eq!(
+        ds.find_orphans(false).unwrap(),
+        vec![bucket.join(".renamed.lock")]
+    );
+    assert_eq!(
+        ds.get_variant("plain", ContentEncoding::Gzip).unwrap(),
+        None
+    );
+
+    teardown(vec!["fskv_test_put_with_variants"]);
+}
+
+#[test]
+fn test_delete_where() {
+    let ds = Store::new("fskv_test_delete_where", true).unwrap();
+    for i in 0..20 {
+        assert_eq!(ds.put(&format!("tmp_{}", i), "x").is_ok(), true);
+        assert_eq!(ds.put(&format!("keep_{}", i), "x").is_ok(), true);
+    }
+    let bucket = key_file("fskv_test_delete_where", "tmp_0")
+        .parent()
+        .unwrap()
+        .to_path_buf();
+
+    let deleted = ds.delete_where(|key| key.starts_with("tmp_")).unwrap();
+    assert_eq!(deleted, 20);
+    let mut keys = ds.keys().collect::<Result<Vec<_>, _>>().unwrap();
+    keys.sort();
+    let mut expected = (0..20).map(|i| format!("keep_{}", i)).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(keys, expected);
+    // the buckets emptied are gone, and come back when needed
+    assert_eq!(bucket.exists(), false);
+    assert_eq!(ds.put("tmp_0", "y").is_ok(), true);
+    assert_eq!(ds.get("tmp_0").unwrap(), "y");
+    assert_eq!(ds.update("tmp_1", "y").is_ok(), true);
+    assert_eq!(ds.append("tmp_2", "y").is_ok(), true);
+    assert_eq!(ds.get("tmp_2").unwrap(), "y");
+
+    assert_eq!(
+        ds.delete_where(|key| key.starts_with("nothing")).unwrap(),
+        0
+    );
+
+    teardown(vec!["fskv_test_delete_where"]);
+}
+
+#[test]
+fn test_ring_buckets() {
+    // where each key lands in stores with `buckets` ring buckets
+    fn layout(buckets: u32) -> HashMap<String, PathBuf> {
+        let root = format!("fskv_test_ring_buckets_{}", buckets);
+        let ds = StoreBuilder::new(&root)
+            .create(true)
+            .ring_buckets(buckets)
+            .build()
+            .unwrap();
+        for i in 0..2000 {
+            ds.put(&format!("key{}", i), "v").unwrap();
+        }
+        let root = fs::canonicalize(&root).unwrap();
+        ds.key_paths()
+            .map(|entry| {
+                let (key, path) = entry.unwrap();
+                let bucket = path.parent().unwrap().strip_prefix(&root).unwrap();
+                (key, bucket.to_path_buf())
+            })
+            .collect()
+    }
+
+    let before = layout(64);
+    let after = layout(65);
+    assert_eq!(before.len(), 2000);
+    assert_eq!(before.values().collect::<HashSet<_>>().len(), 64);
+    // about 1 in 65 keys move, where resizing the MD5 tree moves them all
+    let moved = before
+        .iter()
+        .filter(|(key, bucket)| after[*key] != **bucket)
+        .count();
+    assert_eq!(moved > 0 && moved < 2000 / 10, true);
+
+    // the number of buckets is fixed when the store is created
+    let ds = StoreBuilder::new("fskv_test_ring_buckets_64")
+        .ring_buckets(65)
+        .build();
+    assert_eq!(ds.is_err(), true);
+    let ds = Store::new("fskv_test_ring_buckets_64", false).unwrap();
+    assert_eq!(ds.get("key7").unwrap(), "v");
+    assert_eq!(
+        StoreBuilder::new("fskv_test_ring_buckets_0")
+            .create(true)
+            .ring_buckets(0)
+            .build()
+            .is_err(),
+        true
+    );
+    assert_eq!(
+        StoreBuilder::new("fskv_test_ring_buckets_0")
+            .create(true)
+            .ring_buckets(4097)
+            .build()
+            .is_err(),
+        true
+    );
+
+    teardown(vec![
+        "fskv_test_ring_buckets_64",
+        "fskv_test_ring_buckets_65",
+    ]);
+}
+
+#[test]
+fn test_io_kind() {
+    let backend = Arc::new(FaultyBackend::default());
+    let ds = StoreBuilder::new("fskv_test_io_kind")
+        .create(true)
+        .backend(Arc::clone(&backend))
+        .build()
+        .unwrap();
+
+    // as if the store's directory weren't writable
+    backend.fail_dirs.store(true, Ordering::SeqCst);
+    let e = ds.put("k", "v").unwrap_err();
+    assert_eq!(e.io_kind(), Some(std::io::ErrorKind::PermissionDenied));
+    backend.fail_dirs.store(false, Ordering::SeqCst);
+    // errors that aren't the file system's have no kind
+    assert_eq!(ds.get("k").unwrap_err().io_kind(), None);
+    assert_eq!(ds.put("", "v").unwrap_err().io_kind(), None);
+
+    teardown(vec!["fskv_test_io_kind"]);
+}
+
+#[test]
+fn test_diff() {
+    let old = Store::new("fskv_test_diff_old", true).unwrap();
+    let new = Store::new("fskv_test_diff_new", true).unwrap();
+    for key in ["same", "removed", "changed"] {
+        assert_eq!(old.put(key, key).is_ok(), true);
+    }
+    assert_eq!(new.put("same", "same").is_ok(), true);
+    assert_eq!(new.put("changed", "changed!").is_ok(), true);
+    assert_eq!(new.put("added", "added").is_ok(), true);
+
+    let diff = new
+        .diff(&old)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        diff,
+        vec![
+            Diff::OnlyInSelf("added".to_string()),
+            Diff::Changed("changed".to_string()),
+            Diff::OnlyInOther("removed".to_string()),
+        ]
+    );
+    assert_eq!(old.diff(&old).unwrap().count(), 0);
+
+    teardown(vec!["fskv_test_diff_old", "fskv_test_diff_new"]);
+}
+
+#[test]
+fn test_copy_prefix() {
+    let ds = Store::new("fskv_test_copy_prefix", true).unwrap();
+    assert_eq!(ds.put("a:1", "one").is_ok(), true);
+    assert_eq!(ds.put("a:2", "two").is_ok(), true);
+    assert_eq!(ds.put("other", "three").is_ok(), true);
+
+    assert_eq!(ds.copy_prefix("a:", "b:").unwrap(), 2);
+    assert_eq!(ds.get("b:1").unwrap(), "one");
+    assert_eq!(ds.get("b:2").unwrap(), "two");
+    assert_eq!(ds.get("a:1").unwrap(), "one");
+    assert_eq!(ds.get("a:2").unwrap(), "two");
+    assert_eq!(ds.len().unwrap(), 5);
+
+    // keys already there are left alone
+    assert_eq!(ds.update("b:1", "changed").is_ok(), true);
+    assert_eq!(ds.put("a:3", "new").is_ok(), true);
+    assert_eq!(ds.copy_prefix("a:", "b:").unwrap(), 1);
+    assert_eq!(ds.get("b:1").unwrap(), "changed");
+    assert_eq!(ds.get("b:3").unwrap(), "new");
+
+    teardown(vec!["fskv_test_copy_prefix"]);
+}
+
+#[test]
+fn test_value_codecs() {
+    let ds = StoreBuilder::new("fskv_test_value_codecs")
+        .create(true)
+        .value_codecs(true)
+        .build()
+        .unwrap();
+    let json = format!("[{}]", vec!["{\"name\": \"value\"}"; 200].join(", "));
+    let size = |root, key| fs::metadata(key_file(root, key)).unwrap().len();
+
+    // one byte of header on top of the value, compressed or not
+    assert_eq!(
+        ds.put_with_codec("json", &json, Some(ContentEncoding::Gzip))
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.put("plain", "not worth it").is_ok(), true);
+    assert_eq!(
+        ds.put_with_codec("zstd", &json, Some(ContentEncoding::Zstd))
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("json").unwrap(), json);
+    assert_eq!(ds.get("zstd").unwrap(), json);
+    assert_eq!(ds.get_bytes("json").unwrap(), json.as_bytes());
+    assert_eq!(ds.get("plain").unwrap(), "not worth it");
+    assert_eq!(
+        size("fskv_test_value_codecs", "json") < json.len() as u64 / 10,
+        true
+    );
+    assert_eq!(
+        size("fskv_test_value_codecs", "zstd") < json.len() as u64 / 10,
+        true
+    );
+    assert_eq!(
+        size("fskv_test_value_codecs", "plain"),
+        "not worth it".len() as u64 + 1
+    );
+
+    assert_eq!(
+        ds.update_with_codec("plain", &json, Some(ContentEncoding::Brotli))
+            .is_ok(),
+        true
+    );
+    assert_eq!(ds.get("plain").unwrap(), json);
+    assert_eq!(
+        size("fskv_test_value_codecs", "plain") < json.len() as u64 / 10,
+        true
+    );
+    // appends only go to values that aren't compressed
+    assert_eq!(ds.append("json", "more").is_err(), true);
+    assert_eq!(ds.append("log", "one").is_ok(), true);
+    assert_eq!(ds.append("log", " two").is_ok(), true);
+    assert_eq!(ds.get("log").unwrap(), "one two");
+
+    // copies out of the store have the values as they were written
+    let clone = ds
+        .clone_into(StoreBuilder::new("fskv_test_value_codecs_clone"))
+        .unwrap();
+    assert_eq!(clone.get("json").unwrap(), json);
+    assert_eq!(
+        size("fskv_test_value_codecs_clone", "json"),
+        json.len() as u64
+    );
+    let mut tar = Vec::new();
+    assert_eq!(ds.export_where(&mut tar, |key| key == "json").unwrap(), 1);
+    let imported = Store::new("fskv_test_value_codecs_import", true).unwrap();
+    assert_eq!(imported.import_tar(&tar[..]).is_ok(), true);
+    assert_eq!(imported.get("json").unwrap(), json);
+
+    // the store keeps them, and older versions of the crate can't read it
+    let marker = fs::read_to_string("fskv_test_value_codecs/.fskv").unwrap();
+    assert_eq!(marker.contains("value_codecs=true"), true);
+    assert_eq!(marker.contains("format_version=2"), true);
+    let ds = Store::new("fskv_test_value_codecs", false).unwrap();
+    assert_eq!(ds.get("json").unwrap(), json);
+
+    // stores without them can't compress values one by one
+    let plain = Store::new("fskv_test_value_codecs_plain", true).unwrap();
+    match plain.put_with_codec("json", &json, Some(ContentEncoding::Gzip)) {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(plain.put_with_codec("json", &json, None).is_ok(), true);
+    assert_eq!(
+        size("fskv_test_value_codecs_plain", "json"),
+        json.len() as u64
+    );
+    let reopened = StoreBuilder::new("fskv_test_value_codecs_plain")
+        .value_codecs(true)
+        .build();
+    match reopened {
+        Err(FskvError::ConfigMismatch(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    teardown(vec![
+        "fskv_test_value_codecs",
+        "fskv_test_value_codecs_plain",
+        "fskv_test_value_codecs_clone",
+        "fskv_test_value_codecs_import",
+    ]);
+}